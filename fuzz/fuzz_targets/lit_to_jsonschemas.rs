@@ -0,0 +1,31 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use concourse_jsonschema_generator::schema::serialize::{self, Draft};
+use concourse_jsonschema_generator::{convert, lit};
+use libfuzzer_sys::fuzz_target;
+
+// Any input must come out as a parse error, conversion errors or a serialized schema, never as a
+// panic
+fuzz_target!(|data: &[u8]| {
+  if let Ok(contents) = std::str::from_utf8(data) {
+    if let Ok(doc) = lit::parse(contents) {
+      let opts = convert::Options::default();
+      if let Ok(schemas) = convert::to_jsonschemas(&doc, "fuzz.lit", contents, &opts) {
+        let schema_docs = schemas
+          .into_iter()
+          .map(|schema| (schema.schema_name.clone(), schema))
+          .collect::<HashMap<_, _>>();
+        for draft in [Draft::Draft04, Draft::Draft202012] {
+          let opts = serialize::Options {
+            draft,
+            ..serialize::Options::default()
+          };
+          let schema = serialize::build_schema(&schema_docs, &opts);
+          let _ = serialize::write_json(&schema, &mut std::io::sink(), &opts);
+        }
+      }
+    }
+  }
+});
@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::fetch::is_url;
+
+/// Name of the config file looked up in the working directory
+pub const CONFIG_FILE: &str = "concourse-schema.toml";
+
+/// Project defaults for the command line, which flags override
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// Litfiles, as paths, globs or URLs. Relative ones are relative to the config file.
+  pub litfiles: Vec<String>,
+  pub schema: Option<String>,
+  pub draft: Option<String>,
+  /// Output format, as for `--emit`
+  pub format: Option<String>,
+  pub indent: Option<usize>,
+}
+
+/// Reads the config file at `path`. Without a path, `concourse-schema.toml` is read if the working
+/// directory has one.
+pub fn read_config(path: Option<&str>) -> Result<Config, String> {
+  let path = match path {
+    Some(path) => path,
+    None if Path::new(CONFIG_FILE).exists() => CONFIG_FILE,
+    None => return Ok(Config::default()),
+  };
+
+  let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+  let mut config: Config = toml::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+
+  let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+  config.litfiles = expand_litfiles(&config.litfiles, dir)?;
+  Ok(config)
+}
+
+/// Resolves the litfile globs of a config file in `dir`, in order, keeping URLs as they are
+fn expand_litfiles(litfiles: &[String], dir: &Path) -> Result<Vec<String>, String> {
+  let mut expanded = vec![];
+  for litfile in litfiles {
+    if is_url(litfile) {
+      expanded.push(litfile.clone());
+      continue;
+    }
+
+    let pattern = dir.join(litfile).to_string_lossy().to_string();
+    let paths = glob::glob(&pattern).map_err(|e| format!("litfiles {}: {}", litfile, e))?;
+    let mut matched = paths
+      .filter_map(Result::ok)
+      .map(|path| path.to_string_lossy().to_string())
+      .collect::<Vec<_>>();
+    if matched.is_empty() {
+      return Err(format!("litfiles {} matches no files", litfile));
+    }
+    matched.sort();
+    expanded.extend(matched);
+  }
+  Ok(expanded)
+}
@@ -1,62 +1,379 @@
-use std::collections::HashMap;
+pub mod markdown;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
 
 use itertools::Itertools;
+use peg::{error::ParseError, str::LineCol};
+use semver::Version;
+
+use crate::lit::types::{LitDocument, LitNode, Span};
+use crate::schema::types::{
+  Coercion, Property, PropertyType, Schema, SourceLocation, ORPHAN_PREFIX,
+};
+use markdown::{raw_text, Renderer};
+
+#[derive(Debug, Default)]
+pub struct Options {
+  /// Renders the documentation to Markdown
+  pub renderer: Renderer,
+  /// Dotted type names that should stay refs instead of being coerced to `string`
+  pub keep_dotted: Vec<String>,
+  /// Leave out attributes documented as added after this version
+  pub since: Option<Version>,
+  /// Fail on inconsistent docs, such as a required attribute with a default, instead of warning
+  pub strict: bool,
+  /// Keep the lit markup of each attribute with it
+  pub embed_source: bool,
+  /// Namespace the schemas of each litfile with its file name, unless it has a
+  /// `{- @namespace: name -}` directive
+  pub prefix_by_file: bool,
+  /// Deepest nesting of functions to convert, [`DEFAULT_MAX_DEPTH`] if not set
+  pub max_depth: Option<usize>,
+  /// Keep the comment right before an attribute as a note on it
+  pub comment_notes: bool,
+}
+
+pub use crate::lit::DEFAULT_MAX_DEPTH;
+
+#[derive(Debug)]
+pub struct ConvertError {
+  pub schema_name: String,
+  pub attribute: Option<String>,
+  pub reason: String,
+  /// Line and column (1-based) of the function the error is about
+  pub location: Option<(usize, usize)>,
+}
+
+impl fmt::Display for ConvertError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some((line, column)) = self.location {
+      write!(f, "error at {}:{}: ", line, column)?;
+    }
+    match &self.attribute {
+      Some(attribute) => write!(
+        f,
+        "In schema {}, attribute {}: {}",
+        self.schema_name, attribute, self.reason
+      ),
+      None => write!(f, "In schema {}: {}", self.schema_name, self.reason),
+    }
+  }
+}
 
-use crate::lit::types::{LitDocument, LitNode};
-use crate::schema::types::{Property, PropertyType, Schema};
+/// State shared by the whole traversal of a single document
+struct Context<'a> {
+  path: &'a str,
+  /// The litfile the document was parsed from, which spans point into
+  contents: &'a str,
+  opts: &'a Options,
+  errors: RefCell<Vec<ConvertError>>,
+}
+
+impl Context<'_> {
+  fn text_to_markdown(&self, nodes: &[LitNode]) -> String {
+    self.opts.renderer.render(nodes)
+  }
 
-pub fn to_jsonschemas(doc: &LitDocument) -> Vec<Schema> {
-  collect_schemas(doc)
+  /// Line and column of a function in the litfile, when the span points into its contents
+  fn location(&self, span: &Span) -> Option<(usize, usize)> {
+    let before = self.contents.get(..span.start)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some((span.line, before[line_start..].chars().count() + 1))
+  }
+}
+
+pub fn to_jsonschemas(
+  doc: &LitDocument,
+  path: &str,
+  contents: &str,
+  opts: &Options,
+) -> Result<Vec<Schema>, Vec<ConvertError>> {
+  let ctx = Context {
+    path,
+    contents,
+    opts,
+    errors: RefCell::new(vec![]),
+  };
+
+  check_depth(doc, opts.max_depth.unwrap_or(DEFAULT_MAX_DEPTH), &ctx).map_err(|e| vec![e])?;
+
+  let doc = &expand_attribute_groups(doc, &ctx);
+
+  let mut schemas = collect_schemas(doc, &ctx);
+
+  // Orphans are collected on a best-effort basis, their errors are only reported as warnings
+  let orphan_ctx = Context {
+    path,
+    contents,
+    opts,
+    errors: RefCell::new(vec![]),
+  };
+  schemas.extend(collect_orphans(doc, false, &orphan_ctx));
+  for e in orphan_ctx.errors.into_inner() {
+    log::warn!("In {}: {}", path, e);
+  }
+
+  let namespace = find_directive(doc, "namespace").or_else(|| {
+    opts.prefix_by_file.then(|| {
+      Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+    })
+  });
+  if let Some(namespace) = namespace {
+    namespace_schemas(&mut schemas, &normalize_schema_name(&namespace));
+  }
+
+  let errors = ctx.errors.into_inner();
+  if errors.is_empty() {
+    Ok(schemas)
+  } else {
+    Err(errors)
+  }
+}
+
+/// Fails on the first function nested deeper than `max_depth`, naming the schema around it. The
+/// document is walked without recursion, but documents only get here once parsed, which
+/// [`crate::lit::parse_with_max_depth`] limits to the same depth of braces.
+fn check_depth(doc: &LitDocument, max_depth: usize, ctx: &Context) -> Result<(), ConvertError> {
+  let mut pending = vec![(doc, 1, None::<String>)];
+  while let Some((doc, depth, schema_name)) = pending.pop() {
+    for node in doc {
+      let (name, args, span) = match node {
+        LitNode::Fn(name, args, span) => (name, args, span),
+        _ => continue,
+      };
+      if depth > max_depth {
+        return Err(ConvertError {
+          schema_name: schema_name.unwrap_or_else(|| "(top level)".to_string()),
+          attribute: None,
+          reason: format!(
+            "\\{} is nested {} functions deep, more than --max-depth {}",
+            name, depth, max_depth
+          ),
+          location: ctx.location(span),
+        });
+      }
+      let schema_name = match args.first() {
+        Some(arg) if name == "schema" || name == "schema-group" => {
+          Some(normalize_schema_name(&ctx.text_to_markdown(arg)))
+        }
+        _ => schema_name.clone(),
+      };
+      pending.extend(args.iter().map(|arg| (arg, depth + 1, schema_name.clone())));
+    }
+  }
+  Ok(())
+}
+
+/// Prefixes the names of the schemas of a litfile with `namespace.`, and the refs to them, so
+/// schemas of the same name in other litfiles don't replace them. `pipeline` keeps its name, as
+/// the root of the schema refers to it.
+fn namespace_schemas(schemas: &mut [Schema], namespace: &str) {
+  let names = schemas
+    .iter()
+    .map(|schema| schema.schema_name.clone())
+    .filter(|name| name != "pipeline")
+    .collect::<HashSet<_>>();
+  let rename = |name: &mut String| {
+    if names.contains(name) {
+      *name = match name.strip_prefix(ORPHAN_PREFIX) {
+        Some(orphan) => format!("{}{}.{}", ORPHAN_PREFIX, namespace, orphan),
+        None => format!("{}.{}", namespace, name),
+      };
+    }
+  };
+
+  for schema in schemas {
+    rename(&mut schema.schema_name);
+    schema.group_members.iter_mut().for_each(rename);
+    schema.extends.iter_mut().for_each(rename);
+    for prop in schema.properties.values_mut().chain(
+      schema
+        .layers
+        .iter_mut()
+        .flat_map(|layer| layer.values_mut()),
+    ) {
+      rename_property_refs(prop, &rename);
+    }
+  }
+}
+
+fn rename_property_refs(prop: &mut Property, rename: &dyn Fn(&mut String)) {
+  rename_refs(&mut prop.type_name, rename);
+  for attribute in prop.attributes.values_mut() {
+    rename_property_refs(attribute, rename);
+  }
+}
+
+fn rename_refs(prop_type: &mut PropertyType, rename: &dyn Fn(&mut String)) {
+  match prop_type {
+    PropertyType::Ref(name) => rename(name),
+    PropertyType::OneOf(types) => types.iter_mut().for_each(|t| rename_refs(t, rename)),
+    PropertyType::ArrayOf(inner) | PropertyType::Not(inner) => rename_refs(inner, rename),
+    PropertyType::Dict { key, value } => {
+      rename_refs(key, rename);
+      rename_refs(value, rename);
+    }
+    PropertyType::Constant(_, _) => {}
+  }
+}
+
+/// Replaces every `\use-attrs{name}` with the attributes of the matching `\def-attrs{name}{...}`,
+/// wherever either appears in the document. The definitions themselves are dropped.
+fn expand_attribute_groups(doc: &LitDocument, ctx: &Context) -> LitDocument {
+  let mut groups = HashMap::new();
+  collect_attribute_groups(doc, &mut groups, ctx);
+  expand_uses(doc, &groups, None, &mut vec![], ctx)
+}
+
+fn collect_attribute_groups<'a>(
+  doc: &'a LitDocument,
+  groups: &mut HashMap<String, &'a LitDocument>,
+  ctx: &Context,
+) {
+  for node in doc {
+    match node {
+      LitNode::Fn(name, args, span) if name == "def-attrs" && args.len() == 2 => {
+        let group_name = raw_text(&args[0]).trim().to_string();
+        if groups.insert(group_name.clone(), &args[1]).is_some() {
+          ctx.errors.borrow_mut().push(ConvertError {
+            schema_name: group_name,
+            attribute: None,
+            reason: "attribute group is defined more than once".to_string(),
+            location: ctx.location(span),
+          });
+        }
+      }
+      LitNode::Fn(_, args, _) => {
+        for arg in args {
+          collect_attribute_groups(arg, groups, ctx);
+        }
+      }
+      _ => {}
+    }
+  }
+}
+
+fn expand_uses(
+  doc: &LitDocument,
+  groups: &HashMap<String, &LitDocument>,
+  schema_name: Option<&str>,
+  expanding: &mut Vec<String>,
+  ctx: &Context,
+) -> LitDocument {
+  doc
+    .iter()
+    .flat_map(|node| match node {
+      LitNode::Fn(name, _, _) if name == "def-attrs" => vec![],
+      LitNode::Fn(name, args, span) if name == "use-attrs" => {
+        let group_name = args
+          .first()
+          .map(|arg| raw_text(arg).trim().to_string())
+          .unwrap_or_default();
+        let error = |reason: String| ConvertError {
+          schema_name: schema_name.unwrap_or(&group_name).to_string(),
+          attribute: None,
+          reason,
+          location: ctx.location(span),
+        };
+
+        if expanding.contains(&group_name) {
+          let cycle = expanding.iter().chain([&group_name]).join(" -> ");
+          ctx
+            .errors
+            .borrow_mut()
+            .push(error(format!("attribute groups use themselves: {}", cycle)));
+          return vec![];
+        }
+        let group = match groups.get(&group_name) {
+          Some(group) => group,
+          None => {
+            ctx
+              .errors
+              .borrow_mut()
+              .push(error(format!("unknown attribute group {}", group_name)));
+            return vec![];
+          }
+        };
+
+        expanding.push(group_name);
+        let expanded = expand_uses(group, groups, schema_name, expanding, ctx);
+        expanding.pop();
+        expanded
+      }
+      LitNode::Fn(name, args, span) => {
+        let schema_name = if name == "schema" || name == "schema-group" {
+          args.first().map(|arg| raw_text(arg).trim().to_string())
+        } else {
+          schema_name.map(String::from)
+        };
+        let args = args
+          .iter()
+          .map(|arg| expand_uses(arg, groups, schema_name.as_deref(), expanding, ctx))
+          .collect();
+        vec![LitNode::Fn(name.clone(), args, *span)]
+      }
+      other => vec![other.clone()],
+    })
+    .collect()
 }
 
 fn extend_child_properties(
   child_schemas: &mut Vec<Schema>,
   attributes: &HashMap<String, Property>,
 ) {
-  if attributes.len() > 0 {
+  if !attributes.is_empty() {
     for child in child_schemas {
       if !child.is_group_member {
         continue;
       }
       let child_props = &mut child.properties;
-      child_props.extend(attributes.clone().into_iter());
+      child_props.extend(attributes.clone());
     }
   }
 }
 
-fn collect_schemas(doc: &LitDocument) -> Vec<Schema> {
+fn collect_schemas(doc: &LitDocument, ctx: &Context) -> Vec<Schema> {
   doc
     .iter()
     .flat_map(|node| match node {
       LitNode::Text(_) => vec![],
 
-      LitNode::Fn(schema, args) if (schema == "schema") || (schema == "schema-group") => {
+      LitNode::Fn(schema, args, span) if (schema == "schema") || (schema == "schema-group") => {
         let mut found_schemas: Vec<Schema> = vec![];
 
-        let schema_name = text_to_markdown(&args[0])
-          .trim()
-          .replace("`", "_")
-          .replace("-", "_")
-          .replace(" ", "_")
-          .replace("__", "_")
-          .trim_start_matches("_")
-          .to_string();
+        let arity = if schema == "schema" { 2 } else { 3 };
+        if !check_arity(schema, args, arity, None, span, ctx) {
+          return vec![];
+        }
+
+        let schema_name = normalize_schema_name(&ctx.text_to_markdown(&args[0]));
 
         log::debug!("In schema {}", schema_name);
 
+        let schema_body = if schema == "schema" {
+          &args[1]
+        } else {
+          &args[2]
+        };
+
         let (attrs_vec, schemas_vecvec): (Vec<_>, Vec<_>) =
-          collect_attributes(if schema == "schema" {
-            &args[1]
-          } else {
-            &args[2]
-          })
-          .into_iter()
-          .unzip();
+          collect_attributes(schema_body, &schema_name, ctx)
+            .into_iter()
+            .unzip();
 
         let attributes = attrs_vec.into_iter().collect::<HashMap<String, Property>>();
 
-        let inner_schemas = schemas_vecvec.into_iter().flat_map(|svv| svv).collect_vec();
-        let mut child_schemas = args.into_iter().flat_map(collect_schemas).collect_vec();
+        let inner_schemas = schemas_vecvec.into_iter().flatten().collect_vec();
+        let mut child_schemas = args
+          .iter()
+          .flat_map(|arg| collect_schemas(arg, ctx))
+          .collect_vec();
 
         extend_child_properties(&mut child_schemas, &attributes);
 
@@ -68,12 +385,27 @@ fn collect_schemas(doc: &LitDocument) -> Vec<Schema> {
           .map(|s| s.schema_name.clone())
           .collect_vec();
 
-        let has_group_memberes = group_members.len() > 0;
+        let has_group_memberes = !group_members.is_empty();
+        let schema_extensions = extensions(schema_body, &schema_name, None, span, ctx);
 
         found_schemas.push(Schema {
           is_group_member: schema == "schema-group",
-          group_members: group_members,
-          schema_name: schema_name,
+          group_members,
+          schema_name,
+          docs: schema_prose(schema_body, ctx).trim().to_string(),
+          extends: find_directive(schema_body, "extends").map(|base| normalize_schema_name(&base)),
+          discriminator: find_directive(schema_body, "discriminator"),
+          extensions: schema_extensions,
+          resource_type: find_fn(schema_body, "resource-type")
+            .and_then(|args| args.first())
+            .map(|arg| raw_text(arg).trim().to_string()),
+          examples: schema_examples(schema_body),
+          deprecated: find_fn(schema_body, "deprecated").is_some(),
+          layers: vec![],
+          source: SourceLocation {
+            file: ctx.path.to_string(),
+            line: span.line,
+          },
           properties: if has_group_memberes {
             HashMap::new()
           } else {
@@ -87,31 +419,328 @@ fn collect_schemas(doc: &LitDocument) -> Vec<Schema> {
         found_schemas
       }
       // Do not collect schemas from props. Not your job.
-      LitNode::Fn(prop, _) if prop == "required-attribute" || prop == "optional-attribute" => {
+      LitNode::Fn(prop, _, _) if prop == "required-attribute" || prop == "optional-attribute" => {
         vec![]
       }
-      LitNode::Fn(_other_fn, args) => args.into_iter().flat_map(collect_schemas).collect(),
+      LitNode::Fn(_other_fn, args, _) => args
+        .iter()
+        .flat_map(|arg| collect_schemas(arg, ctx))
+        .collect(),
       LitNode::Comment(_) => vec![],
     })
     .collect()
 }
 
-fn collect_attributes(doc: &LitDocument) -> Vec<((String, Property), Vec<Schema>)> {
+/// The comment ending `doc`, give or take whitespace, unless it is a `{- @name: value -}` directive
+fn preceding_comment(doc: &[LitNode]) -> Option<String> {
+  let comment = doc.iter().rev().find_map(|node| match node {
+    LitNode::Text(text) if text.trim().is_empty() => None,
+    LitNode::Comment(comment) => Some(Some(comment.trim())),
+    _ => Some(None),
+  })??;
+  (!comment.starts_with('@') && !comment.is_empty()).then(|| comment.to_string())
+}
+
+/// Collects attributes that don't belong to any schema body or other attribute, each into its own
+/// synthetic schema
+fn collect_orphans(doc: &LitDocument, claimed: bool, ctx: &Context) -> Vec<Schema> {
   doc
     .iter()
     .flat_map(|node| match node {
+      LitNode::Fn(schema, args, _) if (schema == "schema") || (schema == "schema-group") => args
+        .iter()
+        .flat_map(|arg| collect_orphans(arg, true, ctx))
+        .collect_vec(),
+
+      LitNode::Fn(attribute_type, args, span)
+        if (attribute_type == "required-attribute" || attribute_type == "optional-attribute") =>
+      {
+        // Attributes in the docs of another one belong to it
+        let mut orphans = args
+          .iter()
+          .flat_map(|arg| collect_orphans(arg, true, ctx))
+          .collect_vec();
+
+        if !claimed && check_arity(attribute_type, args, 3, None, span, ctx) {
+          let orphan_name = format!("{}{}", ORPHAN_PREFIX, raw_text(&args[0]).trim());
+          let (prop_name, prop) = convert_prop(args, span, attribute_type, &orphan_name, ctx);
+          orphans.push(Schema {
+            schema_name: orphan_name,
+            is_group_member: false,
+            group_members: vec![],
+            docs: "".to_string(),
+            extends: None,
+            discriminator: None,
+            extensions: BTreeMap::new(),
+            resource_type: None,
+            examples: vec![],
+            deprecated: false,
+            layers: vec![],
+            source: SourceLocation {
+              file: ctx.path.to_string(),
+              line: span.line,
+            },
+            properties: HashMap::from([(prop_name, prop)]),
+          });
+        }
+
+        orphans
+      }
+
+      LitNode::Fn(_, args, _) => args
+        .iter()
+        .flat_map(|arg| collect_orphans(arg, claimed, ctx))
+        .collect_vec(),
+
+      _ => vec![],
+    })
+    .collect()
+}
+
+/// The canonical name of a schema, used for its definition, the group members listing it and the
+/// refs to it: `, - and spaces become _, with runs of _ collapsed and leading ones dropped
+pub fn normalize_schema_name(name: &str) -> String {
+  name
+    .trim()
+    .replace(['`', '-', ' '], "_")
+    .split('_')
+    .filter(|part| !part.is_empty())
+    .join("_")
+}
+
+/// Finds a `{- @name: value -}` directive among the top-level comments of a document
+fn find_directive(doc: &LitDocument, name: &str) -> Option<String> {
+  let prefix = format!("@{}:", name);
+  doc.iter().find_map(|node| match node {
+    LitNode::Comment(comment) => comment
+      .trim()
+      .strip_prefix(&prefix)
+      .map(|value| value.trim().to_string()),
+    _ => None,
+  })
+}
+
+/// The `\schema-example{...}`s directly in a schema body
+fn schema_examples(doc: &LitDocument) -> Vec<String> {
+  doc
+    .iter()
+    .filter_map(|node| match node {
+      LitNode::Fn(name, args, _) if name == "schema-example" => Some(
+        args
+          .iter()
+          .map(|arg| raw_text(arg))
+          .collect::<String>()
+          .trim()
+          .to_string(),
+      ),
+      _ => None,
+    })
+    .collect()
+}
+
+/// The code of the `\example-toggle{title}{...}`s directly in attribute docs, each once. Toggles
+/// often repeat an example under another title.
+fn attribute_examples(doc: &LitDocument) -> Vec<String> {
+  doc
+    .iter()
+    .filter_map(|node| match node {
+      LitNode::Fn(name, args, _) if name == "example-toggle" => args.get(1),
+      _ => None,
+    })
+    .map(|body| match find_fn(body, "codeblock") {
+      Some(codeblock) => raw_text(codeblock.get(1).unwrap_or(&vec![])),
+      None => raw_text(body),
+    })
+    .map(|example| example.trim().to_string())
+    .filter(|example| !example.is_empty())
+    .unique()
+    .collect()
+}
+
+/// Reads the vendor keys of a `{- @x: {"x-key": value} -}` directive
+fn extensions(
+  doc: &LitDocument,
+  schema_name: &str,
+  attribute: Option<&str>,
+  span: &Span,
+  ctx: &Context,
+) -> BTreeMap<String, String> {
+  let directive = match find_directive(doc, "x") {
+    Some(directive) => directive,
+    None => return BTreeMap::new(),
+  };
+
+  let error = |reason: String| ConvertError {
+    schema_name: schema_name.to_string(),
+    attribute: attribute.map(String::from),
+    reason,
+    location: ctx.location(span),
+  };
+
+  let keys = match serde_json::from_str::<serde_json::Value>(&directive) {
+    Ok(serde_json::Value::Object(keys)) => keys,
+    _ => {
+      ctx.errors.borrow_mut().push(error(format!(
+        "@x must be a JSON object, found {}",
+        directive
+      )));
+      return BTreeMap::new();
+    }
+  };
+
+  keys
+    .into_iter()
+    .filter(|(key, _)| {
+      let valid = key.starts_with("x-");
+      if !valid {
+        ctx
+          .errors
+          .borrow_mut()
+          .push(error(format!("vendor key {} must start with x-", key)));
+      }
+      valid
+    })
+    .map(|(key, value)| (key, value.to_string()))
+    .collect()
+}
+
+/// Checks that a definition function has the arguments the converter reads, reporting it
+/// otherwise. Schemas are named by their own first argument, attributes by their schema.
+fn check_arity(
+  fn_name: &str,
+  args: &[LitDocument],
+  arity: usize,
+  schema_name: Option<&str>,
+  span: &Span,
+  ctx: &Context,
+) -> bool {
+  if args.len() >= arity {
+    return true;
+  }
+
+  let name = args
+    .first()
+    .map(|arg| raw_text(arg).trim().to_string())
+    .unwrap_or_default();
+  let (schema_name, attribute) = match schema_name {
+    Some(schema_name) => (schema_name.to_string(), Some(name)),
+    None => (normalize_schema_name(&name), None),
+  };
+  ctx.errors.borrow_mut().push(ConvertError {
+    schema_name,
+    attribute,
+    reason: format!(
+      "\\{} expects {} arguments, found {}",
+      fn_name,
+      arity,
+      args.len()
+    ),
+    location: ctx.location(span),
+  });
+  false
+}
+
+/// Functions with a meaning to the converter itself, rather than to the renderer
+const CONVERTER_FNS: [&str; 6] = [
+  "schema",
+  "schema-group",
+  "required-attribute",
+  "optional-attribute",
+  "def-attrs",
+  "use-attrs",
+];
+
+/// Counts the calls of functions that neither the converter nor the renderer handle, which
+/// render as their bare arguments
+pub fn count_unknown_functions(
+  doc: &LitDocument,
+  opts: &Options,
+  counts: &mut BTreeMap<String, usize>,
+) {
+  for node in doc {
+    if let LitNode::Fn(name, args, _) = node {
+      if !CONVERTER_FNS.contains(&name.as_str()) && !opts.renderer.handles(name) {
+        *counts.entry(name.clone()).or_default() += 1;
+      }
+      for arg in args {
+        count_unknown_functions(arg, opts, counts);
+      }
+    }
+  }
+}
+
+fn is_definition_fn(name: &str) -> bool {
+  name == "schema"
+    || name == "schema-group"
+    || name == "required-attribute"
+    || name == "optional-attribute"
+}
+
+fn contains_definitions(doc: &LitDocument) -> bool {
+  doc.iter().any(|node| match node {
+    LitNode::Fn(name, _, _) if is_definition_fn(name) => true,
+    LitNode::Fn(_, args, _) => args.iter().any(contains_definitions),
+    _ => false,
+  })
+}
+
+/// Renders the prose of a schema body, leaving out attributes and nested schemas which are
+/// documented on their own
+fn schema_prose(doc: &LitDocument, ctx: &Context) -> String {
+  doc
+    .iter()
+    .map(|node| match node {
+      LitNode::Fn(name, _, _) if is_definition_fn(name) => "".to_string(),
+      LitNode::Fn(_, args, _) if args.iter().any(contains_definitions) => {
+        args.iter().map(|arg| schema_prose(arg, ctx)).collect()
+      }
+      other => ctx.text_to_markdown(std::slice::from_ref(other)),
+    })
+    .collect()
+}
+
+fn collect_attributes(
+  doc: &LitDocument,
+  schema_name: &str,
+  ctx: &Context,
+) -> Vec<((String, Property), Vec<Schema>)> {
+  doc
+    .iter()
+    .enumerate()
+    .flat_map(|(i, node)| match node {
       LitNode::Text(_) => vec![],
 
-      LitNode::Fn(attribute_type, args)
+      LitNode::Fn(attribute_type, args, span)
         if (attribute_type == "required-attribute" || attribute_type == "optional-attribute") =>
       {
-        let prop_value = convert_prop(&args, attribute_type);
-        let inner_schemas: Vec<_> = args.iter().flat_map(collect_schemas).collect();
+        if !check_arity(attribute_type, args, 3, Some(schema_name), span, ctx) {
+          return vec![];
+        }
+        let mut prop_value = convert_prop(args, span, attribute_type, schema_name, ctx);
+        if ctx.opts.comment_notes {
+          prop_value.1.note = preceding_comment(&doc[..i]);
+        }
+        if !available_since(&prop_value, schema_name, span, ctx) {
+          log::info!(
+            "Leaving out {}.{}, added in {}",
+            schema_name,
+            prop_value.0,
+            prop_value.1.since.as_deref().unwrap_or_default()
+          );
+          return vec![];
+        }
+        let inner_schemas: Vec<_> = args
+          .iter()
+          .flat_map(|arg| collect_schemas(arg, ctx))
+          .collect();
         vec![(prop_value, inner_schemas)]
       }
 
-      LitNode::Fn(other_fn, args) if (other_fn != "schema" && other_fn != "schema-group") => {
-        args.iter().flat_map(collect_attributes).collect::<Vec<_>>()
+      LitNode::Fn(other_fn, args, _) if (other_fn != "schema" && other_fn != "schema-group") => {
+        args
+          .iter()
+          .flat_map(|arg| collect_attributes(arg, schema_name, ctx))
+          .collect::<Vec<_>>()
       }
 
       _ => vec![],
@@ -119,156 +748,853 @@ fn collect_attributes(doc: &LitDocument) -> Vec<((String, Property), Vec<Schema>
     .collect()
 }
 
-fn convert_prop(args: &Vec<Vec<LitNode>>, attribute_type: &String) -> (String, Property) {
-  let prop_name = text_to_markdown(&args[0]).trim().to_string();
+/// Parses a version leniently, padding missing minor/patch components so `7.0` reads as `7.0.0`
+pub fn parse_version(version: &str) -> Result<Version, semver::Error> {
+  let version = version.trim().trim_start_matches('v');
+  let (core, rest) = match version.find(['-', '+']) {
+    Some(i) => version.split_at(i),
+    None => (version, ""),
+  };
+  let mut parts = core.split('.').collect_vec();
+  while parts.len() < 3 {
+    parts.push("0");
+  }
+  Version::parse(&format!("{}{}", parts.join("."), rest))
+}
+
+/// Whether the attribute exists in the targeted version
+fn available_since(
+  (prop_name, prop): &(String, Property),
+  schema_name: &str,
+  span: &Span,
+  ctx: &Context,
+) -> bool {
+  let (target, since) = match (&ctx.opts.since, &prop.since) {
+    (Some(target), Some(since)) => (target, since),
+    _ => return true,
+  };
+
+  match parse_version(since) {
+    Ok(since) => since <= *target,
+    Err(e) => {
+      ctx.errors.borrow_mut().push(ConvertError {
+        schema_name: schema_name.to_string(),
+        attribute: Some(prop_name.clone()),
+        reason: format!("invalid version {}: {}", since, e),
+        location: ctx.location(span),
+      });
+      true
+    }
+  }
+}
+
+/// Finds the arguments of the first call to `name` in a document, without looking into nested
+/// attributes or schemas
+fn find_fn<'a>(doc: &'a LitDocument, name: &str) -> Option<&'a [LitDocument]> {
+  doc.iter().find_map(|node| match node {
+    LitNode::Fn(fn_name, args, _) if fn_name == name => Some(args.as_slice()),
+    LitNode::Fn(fn_name, _, _) if is_definition_fn(fn_name) => None,
+    LitNode::Fn(_, args, _) => args.iter().find_map(|arg| find_fn(arg, name)),
+    _ => None,
+  })
+}
+
+/// The arguments of every call to `name` in a document, looking where [`find_fn`] does
+fn find_fns<'a>(doc: &'a LitDocument, name: &str) -> Vec<&'a [LitDocument]> {
+  doc
+    .iter()
+    .flat_map(|node| match node {
+      LitNode::Fn(fn_name, args, _) if fn_name == name => vec![args.as_slice()],
+      LitNode::Fn(fn_name, _, _) if is_definition_fn(fn_name) => vec![],
+      LitNode::Fn(_, args, _) => args.iter().flat_map(|arg| find_fns(arg, name)).collect(),
+      _ => vec![],
+    })
+    .collect()
+}
+
+fn convert_prop(
+  args: &[LitDocument],
+  span: &Span,
+  attribute_type: &str,
+  schema_name: &str,
+  ctx: &Context,
+) -> (String, Property) {
+  let prop_name = ctx.text_to_markdown(&args[0]).trim().to_string();
   log::debug!("- In prop {}", prop_name);
 
-  let type_name = text_to_markdown(&args[1]).trim().to_string();
+  // An explicit `\attr-type{...}` in any argument takes precedence over the positional type
+  let type_arg = args
+    .iter()
+    .find_map(|arg| find_fn(arg, "attr-type"))
+    .and_then(|type_args| type_args.first())
+    .unwrap_or(&args[1]);
+
+  // Comments are kept verbatim so constants can carry their own docs
+  let type_name = type_arg
+    .iter()
+    .map(|node| match node {
+      LitNode::Comment(comment) => format!("{{-{}-}}", comment),
+      other => ctx.text_to_markdown(std::slice::from_ref(other)),
+    })
+    .collect::<String>()
+    .trim()
+    .to_string();
 
-  let is_list = type_name.starts_with("[");
+  let mut is_list = type_name.starts_with("[");
 
   let documentation = &args[2];
 
+  let dotted = Cell::new(false);
+  let coercion;
+  let mut default = None;
+  let prop_type = match parse_annotation(&type_name, ctx.opts, &dotted) {
+    Ok((prop_type, inline_default)) => {
+      coercion = dotted.get().then_some(Coercion::DottedName);
+      default = inline_default;
+      prop_type
+    }
+    Err(e) => {
+      coercion = Some(Coercion::Unparseable);
+      ctx.errors.borrow_mut().push(ConvertError {
+        schema_name: schema_name.to_string(),
+        attribute: Some(prop_name.clone()),
+        reason: format!("unable to parse type {}: {}", type_name, e),
+        location: ctx.location(span),
+      });
+      PropertyType::Ref("string".to_string())
+    }
+  };
+
+  let prop_type = match find_fn(documentation, "enum") {
+    Some(args) => {
+      let values = args
+        .iter()
+        .map(enum_value)
+        .filter(|value| !value.is_empty())
+        .collect_vec();
+      let error = |reason: String| ConvertError {
+        schema_name: schema_name.to_string(),
+        attribute: Some(prop_name.clone()),
+        reason,
+        location: ctx.location(span),
+      };
+      if values.is_empty() {
+        ctx
+          .errors
+          .borrow_mut()
+          .push(error("\\enum has no values".to_string()));
+        prop_type
+      } else {
+        let original = prop_type.clone();
+        match apply_enum(prop_type, &values) {
+          Ok(prop_type) => prop_type,
+          Err(EnumError::NotString(prop_type)) => {
+            log::warn!(
+              "In schema {}, attribute {}: \\enum only applies to strings, ignoring it",
+              schema_name,
+              prop_name
+            );
+            prop_type
+          }
+          Err(EnumError::OutsideEnum(value)) => {
+            ctx.errors.borrow_mut().push(error(format!(
+              "`{}` is not one of the \\enum values {}",
+              value,
+              values.join(", ")
+            )));
+            original
+          }
+        }
+      }
+    }
+    None => prop_type,
+  };
+
+  // `\list-of` makes a list of a type written without brackets, the way `[type]` parses
+  let prop_type = if !is_list && find_fn(documentation, "list-of").is_some() {
+    is_list = true;
+    PropertyType::OneOf(vec![PropertyType::ArrayOf(Box::new(prop_type))])
+  } else {
+    prop_type
+  };
+
+  let (min_length, max_length) = length_bounds(documentation, schema_name, &prop_name, span, ctx);
+  if (min_length.is_some() || max_length.is_some()) && !is_string_type(&prop_type) {
+    log::warn!(
+      "In schema {}, attribute {}: length bounds only apply to strings, ignoring them",
+      schema_name,
+      prop_name
+    );
+  }
+
+  let multiple_of = multiple_of(documentation, schema_name, &prop_name, span, ctx);
+  let multiple_of = match multiple_of {
+    Some(_) if !is_numeric_type(&prop_type) => {
+      log::warn!(
+        "In schema {}, attribute {}: \\multiple-of only applies to numbers, ignoring it",
+        schema_name,
+        prop_name
+      );
+      None
+    }
+    multiple_of => multiple_of,
+  };
+
+  let prop_extensions = extensions(documentation, schema_name, Some(&prop_name), span, ctx);
+
+  // Legal, but an attribute holding its own schema is more often a copy-paste slip
+  let direct_type = match &prop_type {
+    PropertyType::OneOf(types) if types.len() == 1 => &types[0],
+    prop_type => prop_type,
+  };
+  if matches!(direct_type, PropertyType::Ref(name) if name == schema_name) {
+    log::info!(
+      "In schema {}, attribute {}: refers to its own schema",
+      schema_name,
+      prop_name
+    );
+  }
+
+  let attributes = collect_attributes(documentation, schema_name, ctx)
+    .into_iter()
+    .map(|(attribute, _)| attribute)
+    .collect::<BTreeMap<_, _>>();
+  // The inline attributes are described on their own
+  let docs = if attributes.is_empty() {
+    ctx.text_to_markdown(documentation)
+  } else {
+    schema_prose(documentation, ctx)
+  };
+
+  let mut required_if = vec![];
+  for args in find_fns(documentation, "required-if") {
+    match args {
+      [attribute, value, ..] => {
+        required_if.push((raw_text(attribute).trim().to_string(), enum_value(value)))
+      }
+      _ => ctx.errors.borrow_mut().push(ConvertError {
+        schema_name: schema_name.to_string(),
+        attribute: Some(prop_name.clone()),
+        reason: format!("\\required-if expects 2 arguments, found {}", args.len()),
+        location: ctx.location(span),
+      }),
+    }
+  }
+
+  // A conditionally required attribute is optional otherwise, whichever function documents it
+  let required = attribute_type == "required-attribute" && required_if.is_empty();
+  if required && default.is_some() {
+    let reason = "is required, so its default never applies".to_string();
+    if ctx.opts.strict {
+      ctx.errors.borrow_mut().push(ConvertError {
+        schema_name: schema_name.to_string(),
+        attribute: Some(prop_name.clone()),
+        reason,
+        location: ctx.location(span),
+      });
+    } else {
+      log::warn!(
+        "In schema {}, attribute {}: {}",
+        schema_name,
+        prop_name,
+        reason
+      );
+    }
+  }
+
   log::debug!("- Out prop {}", prop_name);
 
   (
     prop_name,
     Property {
-      required: attribute_type == "required-attribute",
-      docs: text_to_markdown(documentation).trim().to_string(),
-      type_name: parse_type(&type_name.replace("-", "_")),
+      required,
+      docs: docs.trim().to_string(),
+      type_name: prop_type,
       list: is_list,
+      since: find_fn(documentation, "since")
+        .and_then(|args| args.first())
+        .map(|arg| raw_text(arg).trim().to_string()),
+      min_length,
+      max_length,
+      multiple_of,
+      extensions: prop_extensions,
+      coercion,
+      default,
+      content_media_type: find_fn(documentation, "content")
+        .and_then(|args| args.first())
+        .map(|arg| raw_text(arg).trim().to_string()),
+      examples: attribute_examples(documentation),
+      attributes,
+      required_if,
+      note: None,
+      source_text: ctx
+        .opts
+        .embed_source
+        .then(|| ctx.contents.get(span.start..span.end))
+        .flatten()
+        .map(String::from),
     },
   )
 }
 
+/// An `\enum` value, with the backticks around it removed
+pub fn enum_value(arg: &LitDocument) -> String {
+  raw_text(arg).trim().trim_matches('`').to_string()
+}
+
+/// Why an `\enum` can't apply to a type
+enum EnumError {
+  /// The type isn't a string, it is given back
+  NotString(PropertyType),
+  /// The type is a single constant the enum doesn't allow
+  OutsideEnum(String),
+}
+
+/// Restricts a string type to the values of an `\enum`, adding them to a type that already is an
+/// enum. A single constant stays as it is, if the enum allows it.
+fn apply_enum(prop_type: PropertyType, values: &[String]) -> Result<PropertyType, EnumError> {
+  let constants = || {
+    values
+      .iter()
+      .map(|value| PropertyType::Constant(value.clone(), None))
+  };
+
+  match prop_type {
+    PropertyType::Ref(name) if name == "string" => Ok(PropertyType::OneOf(constants().collect())),
+    PropertyType::ArrayOf(inner) => apply_enum(*inner, values)
+      .map(|inner| PropertyType::ArrayOf(Box::new(inner)))
+      .map_err(|e| match e {
+        EnumError::NotString(inner) => EnumError::NotString(PropertyType::ArrayOf(Box::new(inner))),
+        e => e,
+      }),
+    PropertyType::OneOf(types) if matches!(types.as_slice(), [PropertyType::Constant(_, _)]) => {
+      match &types[0] {
+        PropertyType::Constant(value, _) if !values.contains(value) => {
+          Err(EnumError::OutsideEnum(value.clone()))
+        }
+        _ => Ok(PropertyType::OneOf(types)),
+      }
+    }
+    PropertyType::OneOf(mut types) if types.len() == 1 => {
+      match apply_enum(types.remove(0), values) {
+        Ok(prop_type) => Ok(prop_type),
+        Err(EnumError::NotString(prop_type)) => {
+          Err(EnumError::NotString(PropertyType::OneOf(vec![prop_type])))
+        }
+        Err(e) => Err(e),
+      }
+    }
+    PropertyType::OneOf(mut types)
+      if types
+        .iter()
+        .all(|t| matches!(t, PropertyType::Constant(_, _))) =>
+    {
+      for constant in constants() {
+        if let PropertyType::Constant(value, _) = &constant {
+          if !types
+            .iter()
+            .any(|t| matches!(t, PropertyType::Constant(v, _) if v == value))
+          {
+            types.push(constant);
+          }
+        }
+      }
+      Ok(PropertyType::OneOf(types))
+    }
+    other => Err(EnumError::NotString(other)),
+  }
+}
+
+/// Whether a property type is a string, or a list of strings, that length bounds can apply to
+fn is_string_type(prop_type: &PropertyType) -> bool {
+  match prop_type {
+    PropertyType::Ref(name) => name == "string",
+    PropertyType::ArrayOf(inner) => is_string_type(inner),
+    PropertyType::OneOf(types) if types.len() == 1 => is_string_type(&types[0]),
+    _ => false,
+  }
+}
+
+fn is_numeric_type(prop_type: &PropertyType) -> bool {
+  match prop_type {
+    PropertyType::Ref(name) => name == "number" || name == "integer",
+    PropertyType::ArrayOf(inner) => is_numeric_type(inner),
+    PropertyType::OneOf(types) if types.len() == 1 => is_numeric_type(&types[0]),
+    _ => false,
+  }
+}
+
+/// Reads the positive number of a `\multiple-of{n}` in the attribute docs
+fn multiple_of(
+  documentation: &LitDocument,
+  schema_name: &str,
+  prop_name: &str,
+  span: &Span,
+  ctx: &Context,
+) -> Option<String> {
+  let text = raw_text(find_fn(documentation, "multiple-of")?.first()?)
+    .trim()
+    .to_string();
+  match text.parse::<f64>() {
+    Ok(n) if n.is_finite() && n > 0.0 => Some(text),
+    _ => {
+      ctx.errors.borrow_mut().push(ConvertError {
+        schema_name: schema_name.to_string(),
+        attribute: Some(prop_name.to_string()),
+        reason: format!("\\multiple-of must be a positive number, found {}", text),
+        location: ctx.location(span),
+      });
+      None
+    }
+  }
+}
+
+/// Reads the bounds of a `\length{min}{max}` in the attribute docs, either of which may be empty
+fn length_bounds(
+  documentation: &LitDocument,
+  schema_name: &str,
+  prop_name: &str,
+  span: &Span,
+  ctx: &Context,
+) -> (Option<u64>, Option<u64>) {
+  let args = match find_fn(documentation, "length") {
+    Some(args) => args,
+    None => return (None, None),
+  };
+
+  let error = |reason: String| ConvertError {
+    schema_name: schema_name.to_string(),
+    attribute: Some(prop_name.to_string()),
+    reason,
+    location: ctx.location(span),
+  };
+
+  let bound = |arg: Option<&LitDocument>| {
+    let text = arg
+      .map(|arg| raw_text(arg).trim().to_string())
+      .unwrap_or_default();
+    if text.is_empty() {
+      return None;
+    }
+    match text.parse::<u64>() {
+      Ok(bound) => Some(bound),
+      Err(_) => {
+        ctx
+          .errors
+          .borrow_mut()
+          .push(error(format!("invalid length bound {}", text)));
+        None
+      }
+    }
+  };
+
+  let (min, max) = (bound(args.first()), bound(args.get(1)));
+
+  if let (Some(min), Some(max)) = (min, max) {
+    if min > max {
+      ctx.errors.borrow_mut().push(error(format!(
+        "minimum length {} is greater than maximum length {}",
+        min, max
+      )));
+      return (None, None);
+    }
+  }
+
+  (min, max)
+}
+
 peg::parser! {
-  grammar lit_type_parser() for str {
+  grammar lit_type_parser(keep_dotted: &[String], dotted: &Cell<bool>) for str {
 
     pub rule lit_type() -> PropertyType
       = union_type() / non_union_type()
 
+    /// A type, optionally followed by its default as in ``int (default: `0`)``
+    pub rule annotation() -> (PropertyType, Option<String>)
+      = prop_type:lit_type() default:inline_default()? _ { (prop_type, default) }
+
+    rule inline_default() -> String
+      = _ "(" _ "default:" _ "`" value:$([^ '`']*) "`" _ ")" { value.to_string() }
+
     rule non_union_type() -> PropertyType
-      = array_type() / dictionary_type() / constant_type() / ref_type()
+      = not_type() / array_type() / dictionary_type() / constant_type() / ref_type()
+
+    rule not_type() -> PropertyType
+      = "!" _ inner_type:non_union_type() { PropertyType::Not(Box::new(inner_type)) }
 
     rule array_type() -> PropertyType
-      = "[" inner_type:lit_type() "]" { PropertyType::ArrayOf(Box::new(inner_type)) }
+      = "[" _ inner_type:lit_type() _ "]" { PropertyType::ArrayOf(Box::new(inner_type)) }
 
     rule union_type() -> PropertyType =
       inner_types:(non_union_type() ++ (_ "|" _)) { PropertyType::OneOf(inner_types) }
 
-    rule _ = [' ' | '\n']*;
+    rule _ = [' ' | '\t' | '\r' | '\n']*;
 
     rule key_or_value_string() -> String
-      = name:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_']+) { String::from(name) }
+      = name:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-']+) { String::from(name) }
 
     rule type_identifier() -> String
       = name:$(['a'..='z' | 'A'..='Z' | '_']+) { String::from(name) }
 
     rule dictionary_type() -> PropertyType
-      = "{" _ key_or_value_string() _ ":" _ key_or_value_string() "}" { PropertyType::Dict }
+      = "{" _ key:lit_type() _ ":" _ value:lit_type() _ "}" {
+        PropertyType::Dict { key: Box::new(key), value: Box::new(value) }
+      }
 
     rule constant_type() -> PropertyType
-      = "`" value:key_or_value_string() "`" { PropertyType::Constant(value) }
+      = "`" value:key_or_value_string() "`" docs:constant_docs()? {
+        PropertyType::Constant(value, docs)
+      }
+
+    rule constant_docs() -> String
+      = _ "{-" docs:$((!"-}" [_])*) "-}" { docs.trim().to_string() }
 
     rule ref_type() -> PropertyType
       = name:key_or_value_string() {
-        PropertyType::Ref(
-          if name.contains(".") { "string".to_string() } else { name }
-        )
+        let name = normalize_schema_name(&name);
+        if name.contains('.') && !keep_dotted.contains(&name) {
+          dotted.set(true);
+          PropertyType::Ref("string".to_string())
+        } else {
+          PropertyType::Ref(name)
+        }
       }
 
 
   }
 }
 
-fn parse_type(s: &str) -> PropertyType {
-  match lit_type_parser::lit_type(s) {
-    Ok(res) => res,
-    Err(e) => {
-      eprintln!("Error parsing type: {}", s);
-      eprintln!("{}", e);
-      panic!("Unable to parse type")
-    }
+/// A type annotation that doesn't follow the type grammar
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeParseError(pub ParseError<LineCol>);
+
+impl fmt::Display for TypeParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.0.fmt(f)
   }
 }
 
-pub fn text_to_markdown(nodes: &Vec<LitNode>) -> String {
-  nodes
-    .iter()
-    .map(|n| match n {
-      LitNode::Text(t) => clean_text(t),
-      LitNode::Fn(example_fn, args) if (example_fn == "example-toggle") => {
-        format!(
-          "\n@example {}\n{}",
-          text_to_markdown(&args[0]),
-          text_to_markdown(&args[1])
-        )
-      }
-      LitNode::Fn(codeblock, args) if (codeblock == "codeblock") => {
-        format!(
-          "\n\n{}\n\n",
-          // raw_text(&args[0]).trim(),
-          trim_codeblock(&raw_text(&args[1]))
-        )
-      }
-      LitNode::Fn(code, args) if (code == "code") => {
-        format!("`{}`", raw_text(&args[0]))
-      }
-      LitNode::Fn(bold, args) if (bold == "bold") => {
-        format!("**{}**", text_to_markdown(&args[0]))
-      }
-      LitNode::Fn(warn, args) if (warn == "warn") => text_to_markdown(&args[0]),
-      LitNode::Fn(_any_, args) => args.iter().map(text_to_markdown).collect(),
-      _ => "".to_string(),
-    })
-    .collect::<String>()
-    .to_string()
-    .replace("\\{", "{")
-    .replace("\\}", "}")
+impl std::error::Error for TypeParseError {}
+
+/// Parses a single type annotation such as ``[string | `get`]``. Refs are normalized like schema
+/// names, so `get-step` refers to the `get_step` definition.
+pub fn parse_type(s: &str) -> Result<PropertyType, TypeParseError> {
+  parse_type_with(s, &Options::default(), &Cell::new(false)).map_err(TypeParseError)
 }
 
-pub fn clean_text(text: &str) -> String {
-  text
-    .lines()
-    // TODO: Do not trim beginning of first and end of last
-    .map(|t| " ".to_string() + t.trim() + " ")
-    .map(|t| if t == "" { "\n\n".to_string() } else { t })
-    .collect()
+fn parse_type_with(
+  s: &str,
+  opts: &Options,
+  dotted: &Cell<bool>,
+) -> Result<PropertyType, ParseError<LineCol>> {
+  lit_type_parser::lit_type(s, &normalized_keep_dotted(opts), dotted)
 }
 
-pub fn trim_codeblock(text: &str) -> String {
-  let trim_start_count = text
-    .lines()
-    .filter(|l| l.len() > 0)
-    .map(|s| s.chars().position(|c| c != ' '))
-    .filter(|x| x.is_some())
-    .map(|x| x.unwrap())
-    .min()
-    .unwrap_or(0);
-
-  text
-    .split("\n")
-    .map(|l| {
-      if l.len() > trim_start_count {
-        &l[trim_start_count..]
-      } else {
-        l.trim()
-      }
-    })
-    .map(|l| format!("    {}", l))
-    // .collect_v
-    .join("\n")
-    // .trim()
-    .to_string()
+/// Parses the type argument of an attribute into its type and inline default, setting `dotted`
+/// when a dotted name was coerced to `string`
+fn parse_annotation(
+  s: &str,
+  opts: &Options,
+  dotted: &Cell<bool>,
+) -> Result<(PropertyType, Option<String>), ParseError<LineCol>> {
+  lit_type_parser::annotation(s, &normalized_keep_dotted(opts), dotted)
 }
 
-pub fn raw_text(nodes: &Vec<LitNode>) -> String {
-  nodes
+fn normalized_keep_dotted(opts: &Options) -> Vec<String> {
+  opts
+    .keep_dotted
     .iter()
-    .map(|n| match n {
-      LitNode::Text(t) => t.as_str(),
-      _ => "",
-    })
-    .collect::<String>()
+    .map(|k| normalize_schema_name(k))
+    .collect_vec()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn convert_with(contents: &str, opts: &Options) -> Result<Vec<Schema>, Vec<ConvertError>> {
+    let doc = crate::lit::parse(contents).unwrap();
+    to_jsonschemas(&doc, "test.lit", contents, opts)
+  }
+
+  fn convert(contents: &str) -> Result<Vec<Schema>, Vec<ConvertError>> {
+    convert_with(contents, &Options::default())
+  }
+
+  fn attribute(schemas: &[Schema], schema_name: &str, attribute: &str) -> Property {
+    schemas
+      .iter()
+      .find(|schema| schema.schema_name == schema_name)
+      .and_then(|schema| schema.properties.get(attribute))
+      .cloned()
+      .unwrap()
+  }
+
+  fn constants(values: &[&str]) -> PropertyType {
+    PropertyType::OneOf(
+      values
+        .iter()
+        .map(|value| PropertyType::Constant(value.to_string(), None))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn functions_nested_deeper_than_max_depth_are_an_error() {
+    let contents = format!(r"\schema{{s}}{{{}{}}}", r"\ab{".repeat(5), "}".repeat(5));
+    let opts = Options {
+      max_depth: Some(4),
+      ..Options::default()
+    };
+    let errors = convert_with(&contents, &opts).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].schema_name, "s");
+    assert!(errors[0].reason.contains("nested 5 functions deep"));
+  }
+
+  #[test]
+  fn documents_deeper_than_the_default_depth_are_rejected_before_parsing() {
+    let depth = 20_000;
+    let contents = format!("{}{}", r"\ab{".repeat(depth), "}".repeat(depth));
+    assert!(matches!(
+      crate::lit::parse(&contents),
+      Err(crate::lit::LitParseError::TooDeep { max_depth, .. }) if max_depth == DEFAULT_MAX_DEPTH
+    ));
+  }
+
+  #[test]
+  fn enum_restricts_a_string_attribute() {
+    let schemas =
+      convert(r"\schema{s}{\optional-attribute{mode}{string}{One of \enum{`a`}{`b`}.}}").unwrap();
+    assert_eq!(
+      attribute(&schemas, "s", "mode").type_name,
+      constants(&["a", "b"])
+    );
+  }
+
+  #[test]
+  fn enum_keeps_a_single_constant_it_allows() {
+    let schemas =
+      convert(r"\schema{s}{\optional-attribute{get}{`get`}{\enum{`get`}{`put`}}}").unwrap();
+    assert_eq!(
+      attribute(&schemas, "s", "get").type_name,
+      constants(&["get"])
+    );
+  }
+
+  #[test]
+  fn enum_rejects_a_single_constant_it_doesnt_allow() {
+    let errors = convert(r"\schema{s}{\optional-attribute{get}{`get`}{\enum{`put`}}}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].attribute.as_deref(), Some("get"));
+  }
+
+  #[test]
+  fn schema_with_too_few_arguments_is_an_error() {
+    let errors = convert(r"\schema{s}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].schema_name, "s");
+    assert_eq!(errors[0].reason, r"\schema expects 2 arguments, found 1");
+    assert_eq!(errors[0].location, Some((1, 1)));
+  }
+
+  #[test]
+  fn schema_group_with_too_few_arguments_is_an_error() {
+    let errors = convert(r"\schema-group{g}{members}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].schema_name, "g");
+    assert_eq!(
+      errors[0].reason,
+      r"\schema-group expects 3 arguments, found 2"
+    );
+  }
+
+  #[test]
+  fn attribute_with_too_few_arguments_is_an_error() {
+    let errors = convert("\\schema{s}{\n  \\required-attribute{a}{string}\n}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].schema_name, "s");
+    assert_eq!(errors[0].attribute.as_deref(), Some("a"));
+    assert_eq!(
+      errors[0].reason,
+      r"\required-attribute expects 3 arguments, found 2"
+    );
+    assert_eq!(errors[0].location, Some((2, 3)));
+  }
+
+  #[test]
+  fn orphan_attribute_with_too_few_arguments_is_not_a_conversion_error() {
+    // Orphans are best-effort, their problems are only logged
+    assert!(convert(r"\optional-attribute{a}").is_ok());
+  }
+
+  #[test]
+  fn prefix_by_file_namespaces_schemas_of_the_same_name() {
+    let opts = Options {
+      prefix_by_file: true,
+      ..Options::default()
+    };
+    let convert_file = |path: &str, contents: &str| {
+      let doc = crate::lit::parse(contents).unwrap();
+      to_jsonschemas(&doc, path, contents, &opts).unwrap()
+    };
+    let a = convert_file(
+      "a.lit",
+      r"\schema{pipeline}{\required-attribute{config}{config}{}}\schema{config}{}",
+    );
+    let b = convert_file(
+      "b.lit",
+      r"\schema{config}{\optional-attribute{x}{string}{}}",
+    );
+
+    let names = a
+      .iter()
+      .chain(&b)
+      .map(|schema| schema.schema_name.as_str())
+      .sorted()
+      .collect_vec();
+    assert_eq!(names, ["a.config", "b.config", "pipeline"]);
+    assert_eq!(
+      attribute(&a, "pipeline", "config").type_name,
+      PropertyType::OneOf(vec![PropertyType::Ref("a.config".to_string())])
+    );
+  }
+
+  #[test]
+  fn empty_enum_is_an_error() {
+    let errors = convert(r"\schema{s}{\optional-attribute{mode}{string}{\enum{}}}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].attribute.as_deref(), Some("mode"));
+    assert!(errors[0].reason.contains("no values"));
+  }
+
+  #[test]
+  fn dictionaries_have_typed_keys_and_values() {
+    let one_of = |prop_type| PropertyType::OneOf(vec![prop_type]);
+    let version = one_of(PropertyType::Ref("version".to_string()));
+    assert_eq!(
+      parse_type("{resource_name: [version]}").unwrap(),
+      one_of(PropertyType::Dict {
+        key: Box::new(one_of(PropertyType::Ref("resource_name".to_string()))),
+        value: Box::new(one_of(PropertyType::ArrayOf(Box::new(version)))),
+      })
+    );
+  }
+
+  #[test]
+  fn attr_type_takes_precedence_over_the_positional_type() {
+    let schemas =
+      convert(r"\schema{s}{\optional-attribute{n}{string}{\attr-type{number} How many.}}").unwrap();
+    let n = attribute(&schemas, "s", "n");
+    assert_eq!(
+      n.type_name,
+      PropertyType::OneOf(vec![PropertyType::Ref("number".to_string())])
+    );
+    assert_eq!(n.docs.trim(), "How many.");
+  }
+
+  #[test]
+  fn required_attributes_with_a_default_are_an_error_under_strict() {
+    let contents = r"\schema{s}{\required-attribute{n}{number (default: `1`)}{N.}}";
+    // Only a warning by default
+    assert!(convert(contents).is_ok());
+
+    let opts = Options {
+      strict: true,
+      ..Options::default()
+    };
+    let errors = convert_with(contents, &opts).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].attribute.as_deref(), Some("n"));
+    assert_eq!(
+      errors[0].reason,
+      "is required, so its default never applies"
+    );
+
+    let contents = r"\schema{s}{\optional-attribute{n}{number (default: `1`)}{N.}}";
+    assert!(convert_with(contents, &opts).is_ok());
+  }
+
+  #[test]
+  fn repeated_attribute_examples_are_kept_once() {
+    let schemas = convert(
+      r"\schema{s}{\optional-attribute{n}{number}{N.
+        \example-toggle{Short}{\codeblock{yaml}{{{5}}}}
+        \example-toggle{Again}{\codeblock{yaml}{{{ 5 }}}}
+        \example-toggle{Other}{6}
+      }}",
+    )
+    .unwrap();
+    assert_eq!(attribute(&schemas, "s", "n").examples, ["5", "6"]);
+  }
+
+  #[test]
+  fn multiple_of_only_applies_to_numbers() {
+    let schemas = convert(
+      r"\schema{s}{
+        \optional-attribute{n}{number}{N. \multiple-of{1024}}
+        \optional-attribute{s}{string}{S. \multiple-of{2}}
+      }",
+    )
+    .unwrap();
+    assert_eq!(
+      attribute(&schemas, "s", "n").multiple_of.as_deref(),
+      Some("1024")
+    );
+    // Warned about and ignored
+    assert_eq!(attribute(&schemas, "s", "s").multiple_of, None);
+  }
+
+  #[test]
+  fn multiple_of_must_be_positive() {
+    let errors =
+      convert(r"\schema{s}{\optional-attribute{n}{number}{\multiple-of{0}}}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+      errors[0].reason,
+      r"\multiple-of must be a positive number, found 0"
+    );
+  }
+
+  #[test]
+  fn spaced_out_type_annotations_parse() {
+    let schemas = convert(
+      "\\schema{s}{\\optional-attribute{a}{[ string ]}{A.}\\optional-attribute{b}{[\n  string\n]}{B.}}",
+    )
+    .unwrap();
+    let strings = PropertyType::OneOf(vec![PropertyType::ArrayOf(Box::new(PropertyType::OneOf(
+      vec![PropertyType::Ref("string".to_string())],
+    )))]);
+    assert_eq!(attribute(&schemas, "s", "a").type_name, strings);
+    assert_eq!(attribute(&schemas, "s", "b").type_name, strings);
+    assert!(attribute(&schemas, "s", "a").coercion.is_none());
+
+    // Formatting splits the annotation into several nodes, each rendered on its own
+    let schemas = convert(
+      r"\schema{s}{
+        \optional-attribute{r}{[ \reference{resource} ]}{R.}
+        \optional-attribute{u}{\reference{resource} | [ string ]}{U.}
+      }",
+    )
+    .unwrap();
+    let resource = || PropertyType::Ref("resource".to_string());
+    let r = attribute(&schemas, "s", "r");
+    assert_eq!(
+      r.type_name,
+      PropertyType::OneOf(vec![PropertyType::ArrayOf(Box::new(PropertyType::OneOf(
+        vec![resource()]
+      )))])
+    );
+    assert!(r.list && r.coercion.is_none());
+    let u = attribute(&schemas, "s", "u");
+    assert_eq!(
+      u.type_name,
+      PropertyType::OneOf(vec![
+        resource(),
+        PropertyType::ArrayOf(Box::new(PropertyType::OneOf(vec![PropertyType::Ref(
+          "string".to_string()
+        )])))
+      ])
+    );
+  }
 }
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::lit::types::{LitDocument, LitNode};
+
+/// Renders a function call to Markdown, given the renderer and the function's arguments
+pub type FnHandler = Box<dyn Fn(&Renderer, &[LitDocument]) -> String>;
+
+/// Converts lit documents to Markdown. Functions are rendered by the handler registered under
+/// their name, functions without a handler render as the concatenation of their arguments.
+pub struct Renderer {
+  handlers: HashMap<String, FnHandler>,
+  /// Wrap functions without a handler in HTML comments naming them
+  pub preserve_unknown_fns: bool,
+  /// Decode common HTML entities such as `&amp;` in text
+  pub decode_entities: bool,
+}
+
+impl Renderer {
+  /// A renderer without any handlers registered
+  pub fn empty() -> Self {
+    Renderer {
+      handlers: HashMap::new(),
+      preserve_unknown_fns: false,
+      decode_entities: false,
+    }
+  }
+
+  /// Registers the handler for a function, replacing the previous one if any
+  pub fn register<F>(&mut self, name: &str, handler: F)
+  where
+    F: Fn(&Renderer, &[LitDocument]) -> String + 'static,
+  {
+    self.handlers.insert(name.to_string(), Box::new(handler));
+  }
+
+  /// Whether the function is rendered by a handler: its own, or for rows and cells the one of
+  /// `\table`
+  pub fn handles(&self, name: &str) -> bool {
+    self.handlers.contains_key(name)
+      || (self.handlers.contains_key("table") && TABLE_PARTS.contains(&name))
+  }
+
+  pub fn render(&self, nodes: &[LitNode]) -> String {
+    nodes
+      .iter()
+      .map(|n| match n {
+        LitNode::Text(t) if self.decode_entities => decode_entities(&clean_text(t)),
+        LitNode::Text(t) => clean_text(t),
+        LitNode::Fn(name, args, _) => match self.handlers.get(name) {
+          Some(handler) => handler(self, args),
+          None if self.preserve_unknown_fns => format!(
+            "<!-- \\{} -->{}<!-- /\\{} -->",
+            name,
+            args.iter().map(|arg| self.render(arg)).collect::<String>(),
+            name
+          ),
+          None => args.iter().map(|arg| self.render(arg)).collect(),
+        },
+        _ => "".to_string(),
+      })
+      .collect::<String>()
+      .replace("\\{", "{")
+      .replace("\\}", "}")
+  }
+}
+
+impl Default for Renderer {
+  fn default() -> Self {
+    let mut renderer = Renderer::empty();
+
+    renderer.register("example-toggle", |r, args| {
+      format!(
+        "\n@example {}\n{}",
+        r.render(arg(args, 0)),
+        r.render(arg(args, 1))
+      )
+    });
+    renderer.register("codeblock", |_, args| {
+      format!(
+        "\n\n{}\n\n",
+        // raw_text(arg(args, 0)).trim(),
+        trim_codeblock(&raw_text(arg(args, 1)))
+      )
+    });
+    renderer.register("code", |_, args| format!("`{}`", raw_text(arg(args, 0))));
+    // Emphasis markers only apply when they hug the text
+    renderer.register("bold", |r, args| {
+      format!("**{}**", r.render(arg(args, 0)).trim())
+    });
+    renderer.register("italic", |r, args| {
+      format!("*{}*", r.render(arg(args, 0)).trim())
+    });
+    renderer.register("emph", |r, args| {
+      format!("*{}*", r.render(arg(args, 0)).trim())
+    });
+    renderer.register("strike", |r, args| {
+      format!("~~{}~~", r.render(arg(args, 0)).trim())
+    });
+    renderer.register("strikethrough", |r, args| {
+      format!("~~{}~~", r.render(arg(args, 0)).trim())
+    });
+    renderer.register("warn", |r, args| r.render(arg(args, 0)));
+    renderer.register("table", table_to_markdown);
+    renderer.register("since", |_, args| {
+      format!("(since {})", raw_text(arg(args, 0)).trim())
+    });
+    renderer.register("length", length_to_markdown);
+    renderer.register("multiple-of", |_, args| {
+      format!("(a multiple of {})", raw_text(arg(args, 0)).trim())
+    });
+    renderer.register("required-if", |_, args| {
+      format!(
+        "(required if `{}` is `{}`)",
+        raw_text(arg(args, 0)).trim(),
+        raw_text(arg(args, 1)).trim().trim_matches('`')
+      )
+    });
+    renderer.register("list-of", |r, args| r.render(arg(args, 0)));
+    renderer.register("resource-type", |_, _| "".to_string());
+    renderer.register("schema-example", |_, _| "".to_string());
+    renderer.register("content", |_, _| "".to_string());
+    renderer.register("attr-type", |_, _| "".to_string());
+    // Cross-reference targets are for navigating the docs, not part of the text
+    renderer.register("anchor", |_, _| "".to_string());
+    renderer.register("target", |_, _| "".to_string());
+    renderer.register("deprecated", |r, args| {
+      format!("**Deprecated.** {}", r.render(arg(args, 0)).trim())
+    });
+    renderer.register("enum", |_, args| {
+      format!(
+        "(one of {})",
+        args
+          .iter()
+          .map(|arg| format!("`{}`", crate::convert::enum_value(arg)))
+          .join(", ")
+      )
+    });
+
+    renderer
+  }
+}
+
+fn length_to_markdown(_: &Renderer, args: &[LitDocument]) -> String {
+  let bound = |i: usize| {
+    args
+      .get(i)
+      .map(|arg| raw_text(arg).trim().to_string())
+      .filter(|bound| !bound.is_empty())
+  };
+
+  match (bound(0), bound(1)) {
+    (Some(min), Some(max)) => format!("({} to {} characters)", min, max),
+    (Some(min), None) => format!("(at least {} characters)", min),
+    (None, Some(max)) => format!("(at most {} characters)", max),
+    (None, None) => "".to_string(),
+  }
+}
+
+impl fmt::Debug for Renderer {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Renderer")
+      .field("handlers", &self.handlers.keys().sorted().collect_vec())
+      .field("preserve_unknown_fns", &self.preserve_unknown_fns)
+      .field("decode_entities", &self.decode_entities)
+      .finish()
+  }
+}
+
+/// An argument of a function call, empty when the call doesn't have it
+fn arg(args: &[LitDocument], index: usize) -> &[LitNode] {
+  args.get(index).map(Vec::as_slice).unwrap_or_default()
+}
+
+fn find_fns<'a>(doc: &'a LitDocument, name: &str) -> Vec<&'a [LitDocument]> {
+  doc
+    .iter()
+    .filter_map(|node| match node {
+      LitNode::Fn(fn_name, args, _) if fn_name == name => Some(args.as_slice()),
+      _ => None,
+    })
+    .collect()
+}
+
+/// The functions `\table` reads its content from
+const TABLE_PARTS: [&str; 2] = ["row", "cell"];
+
+/// Renders `\table{\row{\cell{..}..}..}` (or rows with one argument per cell) as a GitHub
+/// Flavored Markdown table, using the first row as the header
+fn table_to_markdown(renderer: &Renderer, args: &[LitDocument]) -> String {
+  let rows = args
+    .iter()
+    .flat_map(|arg| find_fns(arg, "row"))
+    .map(|row_args| {
+      let cells = row_args
+        .iter()
+        .flat_map(|arg| find_fns(arg, "cell"))
+        .collect_vec();
+      let cell_docs = if cells.is_empty() {
+        row_args.iter().collect_vec()
+      } else {
+        cells
+          .iter()
+          .flat_map(|cell_args| cell_args.iter())
+          .collect_vec()
+      };
+      cell_docs
+        .into_iter()
+        .map(|cell| {
+          renderer
+            .render(cell)
+            .split_whitespace()
+            .join(" ")
+            .replace('|', "\\|")
+        })
+        .collect_vec()
+    })
+    .collect_vec();
+
+  let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+  if columns == 0 {
+    return "".to_string();
+  }
+
+  let format_row = |mut cells: Vec<String>| {
+    cells.resize(columns, "".to_string());
+    format!("| {} |", cells.join(" | "))
+  };
+
+  let mut lines = vec![];
+  let mut rows = rows.into_iter();
+  if let Some(header) = rows.next() {
+    lines.push(format_row(header));
+    lines.push(format_row(vec!["---".to_string(); columns]));
+  }
+  lines.extend(rows.map(format_row));
+
+  format!("\n\n{}\n\n", lines.join("\n"))
+}
+
+pub fn clean_text(text: &str) -> String {
+  text
+    .lines()
+    // TODO: Do not trim beginning of first and end of last
+    .map(|t| " ".to_string() + t.trim() + " ")
+    .map(|t| if t.is_empty() { "\n\n".to_string() } else { t })
+    .collect()
+}
+
+/// Decodes the HTML entities commonly found in prose. `&amp;` goes last, so `&amp;lt;` stays
+/// `&lt;`.
+pub fn decode_entities(text: &str) -> String {
+  text
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&apos;", "'")
+    .replace("&nbsp;", " ")
+    .replace("&amp;", "&")
+}
+
+pub fn trim_codeblock(text: &str) -> String {
+  let trim_start_count = text
+    .lines()
+    .filter(|l| !l.is_empty())
+    .filter_map(|s| s.chars().position(|c| c != ' '))
+    .min()
+    .unwrap_or(0);
+
+  text
+    .split("\n")
+    .map(|l| match l.get(trim_start_count..) {
+      Some(rest) if l.len() > trim_start_count => rest,
+      _ => l.trim(),
+    })
+    .map(|l| format!("    {}", l))
+    // .collect_v
+    .join("\n")
+    // .trim()
+    .to_string()
+}
+
+pub fn raw_text(nodes: &[LitNode]) -> String {
+  nodes
+    .iter()
+    .map(|n| match n {
+      LitNode::Text(t) => t.as_str(),
+      _ => "",
+    })
+    .collect::<String>()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(contents: &str) -> String {
+    Renderer::default().render(&crate::lit::parse(contents).unwrap())
+  }
+
+  #[test]
+  fn tables_render_as_markdown_tables() {
+    let table = render(r"\table{\row{\cell{Value}\cell{Meaning}}\row{\cell{`a`}\cell{\bold{A}}}}");
+    assert_eq!(
+      table.trim(),
+      "| Value | Meaning |\n| --- | --- |\n| `a` | **A** |"
+    );
+    // Rows may also hold one argument per cell
+    assert_eq!(
+      render(r"\table{\row{x}{y}\row{1}{2}}"),
+      render(r"\table{\row{\cell{x}\cell{y}}\row{\cell{1}\cell{2}}}")
+    );
+  }
+
+  #[test]
+  fn entities_are_decoded_when_asked_to() {
+    let doc = crate::lit::parse("Fish &amp; chips &lt;3").unwrap();
+    assert_eq!(
+      Renderer::default().render(&doc).trim(),
+      "Fish &amp; chips &lt;3"
+    );
+
+    let renderer = Renderer {
+      decode_entities: true,
+      ..Renderer::default()
+    };
+    assert_eq!(renderer.render(&doc).trim(), "Fish & chips <3");
+    assert_eq!(decode_entities("&amp;lt;"), "&lt;");
+  }
+
+  #[test]
+  fn emphasis_renders_as_markdown() {
+    let rendered =
+      render(r"Both \bold{bold} and \italic{ italic } in \emph{one} \strike{long} paragraph");
+    assert_eq!(
+      rendered.split_whitespace().collect_vec(),
+      [
+        "Both",
+        "**bold**",
+        "and",
+        "*italic*",
+        "in",
+        "*one*",
+        "~~long~~",
+        "paragraph"
+      ]
+    );
+  }
+
+  #[test]
+  fn anchors_and_targets_render_as_nothing() {
+    let rendered = render(r"See \anchor{foo}the docs\target{bar} here");
+    assert_eq!(rendered.split_whitespace().join(" "), "See the docs here");
+  }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+
+use reqwest::blocking::Client;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::StatusCode;
+
+pub fn is_url(path: &str) -> bool {
+  path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// How a litfile is named in messages and source locations. Paths use `/` separators on every
+/// platform, so Windows output matches everything else.
+pub fn display_name(path: &str) -> String {
+  if is_url(path) {
+    return path.to_string();
+  }
+  Path::new(path)
+    .to_string_lossy()
+    .replace(MAIN_SEPARATOR, "/")
+}
+
+/// Reads a litfile from disk, or fetches it when given an http(s) URL. Fetched files are cached in
+/// the temp dir together with their etag, so unchanged files are not downloaded again. When
+/// offline only the cache is used.
+pub fn read_litfile(path: &str, offline: bool) -> Result<String, String> {
+  if !is_url(path) {
+    return fs::read_to_string(Path::new(path)).map_err(|e| e.to_string());
+  }
+
+  let cached_body = cache_path(path, "body");
+  let cached_etag = cache_path(path, "etag");
+
+  if offline {
+    return fs::read_to_string(&cached_body)
+      .map_err(|_| format!("{} is not cached and network access is disabled", path));
+  }
+
+  let etag = fs::read_to_string(&cached_etag)
+    .ok()
+    .filter(|_| cached_body.exists());
+
+  let mut request = Client::new().get(path);
+  if let Some(etag) = &etag {
+    request = request.header(IF_NONE_MATCH, etag);
+  }
+
+  let response = request.send().map_err(|e| e.to_string())?;
+
+  if response.status() == StatusCode::NOT_MODIFIED {
+    log::debug!("{} not modified, using cached copy", path);
+    return fs::read_to_string(&cached_body).map_err(|e| e.to_string());
+  }
+
+  let response = response.error_for_status().map_err(|e| e.to_string())?;
+  let new_etag = response
+    .headers()
+    .get(ETAG)
+    .and_then(|etag| etag.to_str().ok())
+    .map(String::from);
+  let body = response.text().map_err(|e| e.to_string())?;
+
+  if let Some(new_etag) = new_etag {
+    let cached = fs::create_dir_all(cache_dir())
+      .and_then(|_| fs::write(&cached_body, &body))
+      .and_then(|_| fs::write(&cached_etag, new_etag));
+    if let Err(e) = cached {
+      log::warn!("Unable to cache {}: {}", path, e);
+    }
+  }
+
+  Ok(body)
+}
+
+fn cache_dir() -> PathBuf {
+  std::env::temp_dir().join("concourse-jsonschema-generator")
+}
+
+fn cache_path(url: &str, extension: &str) -> PathBuf {
+  let name = url
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect::<String>();
+  cache_dir().join(format!("{}.{}", name, extension))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn display_names_use_forward_slashes() {
+    assert_eq!(display_name("docs/pipeline.lit"), "docs/pipeline.lit");
+    assert_eq!(
+      display_name(r"https://example.com/a\b.lit"),
+      r"https://example.com/a\b.lit"
+    );
+  }
+
+  #[cfg(windows)]
+  #[test]
+  fn windows_paths_display_with_forward_slashes() {
+    assert_eq!(
+      display_name(r"docs\lit\pipeline.lit"),
+      "docs/lit/pipeline.lit"
+    );
+    assert_eq!(
+      display_name(r"C:\docs/pipeline.lit"),
+      "C:/docs/pipeline.lit"
+    );
+  }
+}
@@ -0,0 +1,44 @@
+//! Generates JSON schemas for Concourse pipelines from the Concourse documentation, written in
+//! lit.
+
+pub mod config;
+pub mod convert;
+pub mod fetch;
+pub mod lit;
+pub mod schema;
+pub mod target;
+
+use std::collections::HashMap;
+use std::io;
+
+use serde_json::Value;
+
+pub use convert::{parse_type, TypeParseError};
+pub use schema::types::PropertyType;
+
+/// Builds the JSON schema document for the definitions. `post_process` gets to modify the
+/// document before it is returned, e.g. to add keywords or rename definitions.
+pub fn build_document(
+  schema_docs: &HashMap<String, schema::types::Schema>,
+  opts: &schema::serialize::Options,
+  post_process: Option<&dyn Fn(&mut Value)>,
+) -> Value {
+  let mut document = schema::serialize::build_schema(schema_docs, opts);
+  if let Some(post_process) = post_process {
+    post_process(&mut document);
+  }
+  document
+}
+
+/// Serializes the definitions to a JSON schema, see [`build_document`]
+pub fn generate_schema(
+  schema_docs: &HashMap<String, schema::types::Schema>,
+  opts: &schema::serialize::Options,
+  post_process: Option<&dyn Fn(&mut Value)>,
+) -> serde_json::Result<String> {
+  let document = build_document(schema_docs, opts, post_process);
+  let mut serialized = vec![];
+  schema::serialize::write_json(&document, &mut serialized, opts)?;
+  String::from_utf8(serialized)
+    .map_err(|e| serde_json::Error::io(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
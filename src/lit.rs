@@ -1,10 +1,12 @@
 pub mod types;
 
+use std::fmt;
+
 use peg::{error::ParseError, str::LineCol};
-use types::{LitDocument, LitNode};
+use types::{LitDocument, LitNode, Span};
 
 peg::parser! {
-  grammar lit_parser() for str {
+  grammar lit_parser(line_starts: &[usize]) for str {
     pub rule doc() -> LitDocument
       = l:(LitNode() *) { l }
 
@@ -20,8 +22,9 @@ peg::parser! {
       }
 
     rule functionCall() -> LitNode
-      = "\\" fnName:(functionName()) args:(argument()*) {
-        LitNode::Fn(fnName, args)
+      = start:position!() "\\" fnName:(functionName()) args:(argument()*) end:position!() {
+        let line = line_starts.partition_point(|&line_start| line_start <= start);
+        LitNode::Fn(fnName, args, Span { start, end, line })
       }
 
     rule functionName() -> String
@@ -52,13 +55,214 @@ peg::parser! {
         "\\\\" /
         "\\{" /
         "\\}" /
-        ("{" [^ '}']+ "}")
+        (!"{-" "{" [^ '}']+ "}")
       )+) {
         LitNode::Text(String::from(content))
       }
   }
 }
 
-pub fn parse(contents: &str) -> Result<LitDocument, ParseError<LineCol>> {
-  lit_parser::doc(&contents)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LitParseError {
+  /// A brace (or `{-`, `{{{`) without its counterpart. Such documents often parse, with the
+  /// arguments after the brace read as text, so they are checked beforehand.
+  Unbalanced {
+    brace: &'static str,
+    closed: bool,
+    line: usize,
+    column: usize,
+  },
+  /// Braces nested deeper than the limit, which the parser would recurse through
+  TooDeep {
+    max_depth: usize,
+    line: usize,
+    column: usize,
+  },
+  Syntax(ParseError<LineCol>),
+}
+
+impl fmt::Display for LitParseError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LitParseError::Unbalanced {
+        brace,
+        closed: false,
+        line,
+        column,
+      } => write!(
+        f,
+        "error at {}:{}: {} is never closed with {}",
+        line,
+        column,
+        brace,
+        closing_brace(brace)
+      ),
+      LitParseError::Unbalanced {
+        brace,
+        closed: true,
+        line,
+        column,
+      } => write!(f, "error at {}:{}: {} closes nothing", line, column, brace),
+      LitParseError::TooDeep {
+        max_depth,
+        line,
+        column,
+      } => write!(
+        f,
+        "error at {}:{}: braces nest more than --max-depth {} levels deep",
+        line, column, max_depth
+      ),
+      LitParseError::Syntax(e) => e.fmt(f),
+    }
+  }
+}
+
+impl std::error::Error for LitParseError {}
+
+fn closing_brace(brace: &str) -> &'static str {
+  match brace {
+    "{-" => "-}",
+    "{{{" => "}}}",
+    "{{" => "}}",
+    _ => "}",
+  }
+}
+
+/// Nesting of braces, and of functions, beyond which a document is rejected, as parsing and
+/// conversion recurse per level
+pub const DEFAULT_MAX_DEPTH: usize = 100;
+
+pub fn parse(contents: &str) -> Result<LitDocument, LitParseError> {
+  parse_with_max_depth(contents, DEFAULT_MAX_DEPTH)
+}
+
+/// Parses a document whose braces nest at most `max_depth` levels deep. The parser recurses per
+/// level, so the limit is checked beforehand; limits in the thousands can still exhaust the stack.
+pub fn parse_with_max_depth(
+  contents: &str,
+  max_depth: usize,
+) -> Result<LitDocument, LitParseError> {
+  let line_starts = std::iter::once(0)
+    .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+    .collect::<Vec<_>>();
+  check_braces(contents, &line_starts, max_depth)?;
+  lit_parser::doc(contents, &line_starts).map_err(LitParseError::Syntax)
+}
+
+/// Finds the first brace that isn't closed, the first closing brace without an opening one, or the
+/// first brace nested deeper than `max_depth`. Comments and verbatim arguments are skipped whole,
+/// as their content needn't be balanced.
+fn check_braces(
+  contents: &str,
+  line_starts: &[usize],
+  max_depth: usize,
+) -> Result<(), LitParseError> {
+  let line_column = |start: usize| {
+    let line = line_starts.partition_point(|&line_start| line_start <= start);
+    (
+      line,
+      contents[line_starts[line - 1]..start].chars().count() + 1,
+    )
+  };
+  let unbalanced = |brace, closed, start: usize| {
+    let (line, column) = line_column(start);
+    LitParseError::Unbalanced {
+      brace,
+      closed,
+      line,
+      column,
+    }
+  };
+
+  let mut open = vec![];
+  let mut i = 0;
+  while let Some(c) = contents[i..].chars().next() {
+    let rest = &contents[i..];
+    if c == '\\' {
+      // Escapes and function names
+      i += 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+    } else if rest.starts_with("{-") {
+      i += rest.find("-}").ok_or_else(|| unbalanced("{-", false, i))? + 2;
+    } else if let Some(verbatim) = rest.strip_prefix("{{{") {
+      i += verbatim
+        .find("}}}")
+        .ok_or_else(|| unbalanced("{{{", false, i))?
+        + 6;
+    } else if c == '{' {
+      open.push(i);
+      if open.len() > max_depth {
+        let (line, column) = line_column(i);
+        return Err(LitParseError::TooDeep {
+          max_depth,
+          line,
+          column,
+        });
+      }
+      i += 1;
+    } else if c == '}' {
+      if open.pop().is_none() {
+        let brace = if rest.starts_with("}}") { "}}" } else { "}" };
+        return Err(unbalanced(brace, true, i));
+      }
+      i += 1;
+    } else {
+      i += c.len_utf8();
+    }
+  }
+
+  match open.first() {
+    Some(&start) => {
+      let brace = if contents[start..].starts_with("{{") {
+        "{{"
+      } else {
+        "{"
+      };
+      Err(unbalanced(brace, false, start))
+    }
+    None => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn unbalanced(contents: &str) -> (&'static str, bool, usize, usize) {
+    match parse(contents) {
+      Err(LitParseError::Unbalanced {
+        brace,
+        closed,
+        line,
+        column,
+      }) => (brace, closed, line, column),
+      other => panic!("{:?} parsed as {:?}", contents, other),
+    }
+  }
+
+  #[test]
+  fn unclosed_braces_are_reported_where_they_open() {
+    assert_eq!(unbalanced("\\ab{b}\n\\cd{d"), ("{", false, 2, 4));
+    assert_eq!(
+      unbalanced("\\codeblock{yaml}{{\n  x: 1\n}"),
+      ("{{", false, 1, 17)
+    );
+    assert_eq!(unbalanced("\\ab{{{ verbatim }}"), ("{{{", false, 1, 4));
+    assert_eq!(unbalanced("text {- comment"), ("{-", false, 1, 6));
+  }
+
+  #[test]
+  fn unmatched_closing_braces_are_reported() {
+    assert_eq!(unbalanced("\\ab{b}}"), ("}", true, 1, 7));
+    assert_eq!(unbalanced("\\ab{b}\n}}"), ("}}", true, 2, 1));
+    assert_eq!(
+      parse("\\ab{b}}").unwrap_err().to_string(),
+      "error at 1:7: } closes nothing"
+    );
+  }
+
+  #[test]
+  fn balanced_braces_parse() {
+    let parsed = parse("\\ab{b}{{ c }}\\de{{{ } }}} {- { -}");
+    assert!(parsed.is_ok(), "{:?}", parsed);
+  }
 }
@@ -1,8 +1,17 @@
 pub type LitDocument = Vec<LitNode>;
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+/// Location of a node in the source document. `start` and `end` are byte offsets, `line` is
+/// 1-based.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub line: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum LitNode {
   Text(String),
-  Fn(String, Vec<LitDocument>),
+  Fn(String, Vec<LitDocument>, Span),
   Comment(String),
 }
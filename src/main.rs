@@ -1,49 +1,1458 @@
 use clap::Parser;
-use std::collections::HashMap;
+use regex::Regex;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-mod convert;
-mod lit;
-mod schema;
+use concourse_jsonschema_generator::{build_document, config, convert, fetch, lit, schema, target};
+use schema::serialize::{Draft, EnumCase};
 
 /// Concourse documentation parser
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-  /// Path to the lit files to parse
+  /// Path (or http(s) URL) to the lit files to parse
   #[clap(value_parser)]
   litfiles: Vec<String>,
 
-  /// Existing schema
-  #[clap(short, long, default_value = "schema.json")]
-  schema: String,
+  /// Config file with defaults for the litfiles, schema, draft, format and indent flags [default:
+  /// concourse-schema.toml, if it exists]
+  #[clap(long, value_parser)]
+  config: Option<String>,
+
+  /// Existing schema [default: schema.json]
+  #[clap(short, long, value_parser)]
+  schema: Option<String>,
+
+  /// Write the output to this file instead of stdout. Definitions the file already has but the
+  /// docs don't generate, such as hand-written ones, are kept in the schema written to it.
+  #[clap(short, long, value_parser)]
+  output: Option<String>,
+
+  /// Overwrite `--output` with purely generated output, dropping the definitions only the existing
+  /// file has, hand-written ones included
+  #[clap(long = "no-merge", value_parser)]
+  no_merge: bool,
+
+  /// Dotted type name to keep as a ref instead of coercing it to string (repeatable)
+  #[clap(long = "keep-dotted", value_parser)]
+  keep_dotted: Vec<String>,
+
+  /// Concourse release (6.x or 7.x) whose known-good draft, root policy and type map to start from
+  #[clap(long, value_parser)]
+  target: Option<target::Target>,
+
+  /// JSON Schema draft to target (04, 06, 07, 2019-09 or 2020-12) [default: 04]
+  #[clap(long, value_parser)]
+  draft: Option<Draft>,
+
+  /// Pattern that keys of the `vars` definition must match
+  #[clap(long = "var-name-pattern", value_parser, default_value = schema::serialize::DEFAULT_VAR_NAME_PATTERN)]
+  var_name_pattern: String,
+
+  /// Maximum number of errors to display before summarizing the rest
+  #[clap(long = "max-errors", value_parser, default_value_t = 20)]
+  max_errors: usize,
+
+  /// Write a JSON map from each definition to the file and line it is documented at
+  #[clap(long, value_parser)]
+  index: Option<String>,
+
+  /// Leave out attributes added after this Concourse version
+  #[clap(long, value_parser = parse_version)]
+  since: Option<semver::Version>,
+
+  /// Write the schemas of each litfile to their own file in this directory
+  #[clap(long = "split-by-file", value_parser)]
+  split_by_file: Option<String>,
+
+  /// Never access the network, URL litfiles are only read from the cache
+  #[clap(long, value_parser)]
+  offline: bool,
+
+  /// Leave the synthetic `$orphaned:` definitions out of the schema
+  ///
+  /// Orphans hold attributes documented outside of any schema. No ref points to them (type
+  /// annotations can't name them), so dropping them never leaves a dangling ref. They are still
+  /// collected, so conflicting orphans keep being reported.
+  #[clap(long = "drop-orphans", value_parser)]
+  drop_orphans: bool,
+
+  /// Instead of printing the schema, print how it differs from the given schema file. Exits with 1
+  /// if there are differences.
+  #[clap(long, value_parser)]
+  diff: Option<String>,
+
+  /// Wrap functions the converter doesn't know in `<!-- \fn -->` comments in descriptions
+  #[clap(long = "preserve-unknown-fns", value_parser)]
+  preserve_unknown_fns: bool,
+
+  /// Whether the root of the schema accepts keys the pipeline doesn't document [default: true]
+  #[clap(long = "root-additional-properties", action = clap::ArgAction::Set)]
+  root_additional_properties: Option<bool>,
+
+  /// JSON file mapping definition names to the schema to emit for them, overriding the built-in
+  /// ones for undocumented definitions like `value` or `version`
+  #[clap(long = "type-map", value_parser)]
+  type_map: Option<String>,
+
+  /// JSON file mapping type names to a schema written inline wherever an attribute has that type,
+  /// like `{"port": {"type": "integer", "minimum": 1, "maximum": 65535}}`
+  #[clap(long, value_parser)]
+  primitives: Option<String>,
+
+  /// Give string enum attributes without examples their first value as example, for completion
+  #[clap(long = "auto-enum-examples", value_parser)]
+  auto_enum_examples: bool,
+
+  /// Emit string enums as a `oneOf` of `const`s titled by their docs or title-cased value, for
+  /// labelled completion (draft-06 or later)
+  #[clap(long = "enum-as-oneof", value_parser)]
+  enum_as_oneof: bool,
+
+  /// JSON file mapping definition names, or words in them, to their title, for those
+  /// title-casing gets wrong (acronyms like `url` are capitalized already)
+  #[clap(long = "title-override", value_parser)]
+  title_override: Option<String>,
+
+  /// Write per schema how many attributes have a real type, a description and examples to this
+  /// file, as JSON
+  #[clap(long, value_parser)]
+  coverage: Option<String>,
+
+  /// Write every constant the attribute types allow, mapped to the attributes allowing it, to this
+  /// file, as JSON
+  #[clap(long, value_parser)]
+  glossary: Option<String>,
+
+  /// Write every attribute documented outside of any schema, with its type, whether it is required
+  /// and its source, to this file, as JSON. Lists them even with `--drop-orphans`.
+  #[clap(long, value_parser)]
+  orphans: Option<String>,
+
+  /// Print counts of the attribute types and of the types coerced to string to stderr, as JSON
+  #[clap(long, value_parser)]
+  stats: bool,
+
+  /// Only print the definitions map, without `$schema` or the root `$ref`. Refs keep pointing
+  /// into the definitions key of `--draft`, so splice it under that key.
+  #[clap(long, value_parser)]
+  fragment: bool,
+
+  /// Decode HTML entities such as `&amp;` or `&lt;` in descriptions
+  #[clap(long = "decode-entities", value_parser)]
+  decode_entities: bool,
+
+  /// Generate without printing and compare with `--output`, or `--schema` without it, byte for byte
+  /// as it would be written. Exits with 0 if they match, 1 if they differ (listing the definitions
+  /// that do) and 2 if the schema can't be generated or read.
+  #[clap(long, value_parser)]
+  check: bool,
+
+  /// Fail instead of warning when the schema would be broken, e.g. without any definitions, or the
+  /// docs contradict themselves, e.g. with a default for a required attribute
+  #[clap(long, value_parser)]
+  strict: bool,
+
+  /// Warn about every attribute without a description (an error with `--strict`)
+  #[clap(long = "warn-missing-docs", value_parser)]
+  warn_missing_docs: bool,
+
+  /// Case of enum values in the schema: preserve, lower or upper
+  #[clap(long = "enum-case", value_parser, default_value = "preserve")]
+  enum_case: EnumCase,
+
+  /// Schema file whose definitions types may refer to, refs to them point into that file
+  /// (repeatable)
+  #[clap(long = "external-defs", value_parser)]
+  external_defs: Vec<String>,
+
+  /// URL of a shared base schema that refs to the `--ref-base-names` definitions point into, as
+  /// `<URL>#/definitions/<name>` whatever `--draft`, instead of definitions of their own
+  #[clap(long = "ref-base", value_parser)]
+  ref_base: Option<String>,
+
+  /// Definitions `--ref-base` holds, comma-separated [default: string,number,boolean,integer,value]
+  #[clap(long = "ref-base-names", value_parser, value_delimiter = ',')]
+  ref_base_names: Vec<String>,
+
+  /// Pretty-print the schema with this many spaces of indentation, two if no number is given
+  #[clap(long, value_parser, min_values = 0, default_missing_value = "2")]
+  indent: Option<usize>,
+
+  /// Key to keep the definitions under instead of `definitions` or `$defs` (depending on
+  /// `--draft`)
+  #[clap(long = "definitions-key", value_parser)]
+  definitions_key: Option<String>,
+
+  /// Schema file whose definitions are always emitted, unless the docs document them
+  #[clap(long = "seed-schema", value_parser)]
+  seed_schema: Option<String>,
+
+  /// Also list the keys of closed objects in `propertyNames`, which some editors complete from
+  /// (draft-06 and later)
+  #[clap(long = "emit-property-names", value_parser)]
+  emit_property_names: bool,
+
+  /// Leave `\deprecated` members out of the alternatives of their schema group
+  #[clap(long = "drop-deprecated", value_parser)]
+  drop_deprecated: bool,
+
+  /// Keep a single one of the definitions with identical content, refs to the others point to it
+  #[clap(long = "dedup-definitions", value_parser)]
+  dedup_definitions: bool,
+
+  /// Only generate this schema and the definitions it refers to, transitively (repeatable). The
+  /// root refers to `pipeline`, so without it use `--fragment`.
+  #[clap(long, value_parser)]
+  only: Vec<String>,
+
+  /// Print the definitions as converted from the litfiles, as JSON, instead of a schema
+  #[clap(long = "dump-model", value_parser)]
+  dump_model: bool,
+
+  /// Serialize the definitions of a model written by `--dump-model` (`-` for stdin) instead of
+  /// reading litfiles
+  #[clap(long = "from-model", value_parser)]
+  from_model: Option<String>,
+
+  /// Output format: json for the JSON schema, ts for TypeScript declarations of the same
+  /// definitions. `--check`, `--diff` and `--split-by-file` only apply to json. [default: json]
+  #[clap(long, value_parser)]
+  emit: Option<Emit>,
+
+  /// Cut descriptions longer than this many characters at a word boundary, keeping the full text
+  /// in `x-full-description`
+  #[clap(long = "max-description", value_parser)]
+  max_description: Option<usize>,
+
+  /// Fail on attribute examples written as JSON that don't have the attribute's type
+  #[clap(long = "validate-examples", value_parser)]
+  validate_examples: bool,
+
+  /// Reject litfiles whose braces or functions nest deeper than this [default: 100]
+  #[clap(long = "max-depth", value_parser)]
+  max_depth: Option<usize>,
+
+  /// Prefix the definitions of each litfile, and the refs to them, with `<file name>.`, so
+  /// litfiles documenting schemas of the same name can be combined. A `{- @namespace: name -}`
+  /// comment at the top level of a litfile names its namespace, even without this flag. The
+  /// `pipeline` definition at the root of the schema is never prefixed.
+  #[clap(long = "prefix-by-file", value_parser)]
+  prefix_by_file: bool,
+
+  /// Emit the `{- ... -}` comment right before an attribute as the `$comment` of its schema
+  /// (draft-07 and later)
+  #[clap(long = "comment-notes", value_parser)]
+  comment_notes: bool,
+
+  /// Print the `yaml.schemas` setting that associates pipelines matching this glob with the written
+  /// schema in VS Code (repeatable), for `.vscode/settings.json`. Needs `--output`, or
+  /// `--split-by-file` to associate the file of the pipeline definition.
+  #[clap(long = "emit-vscode-config", value_parser)]
+  emit_vscode_config: Vec<String>,
+
+  /// Keep the lit markup documenting each attribute in the `$comment` of its schema (draft-07 and
+  /// later)
+  #[clap(long = "embed-source", value_parser)]
+  embed_source: bool,
+
+  /// Collapse runs of spaces in descriptions and trim their lines, except in code blocks
+  #[clap(long = "minify-descriptions", value_parser)]
+  minify_descriptions: bool,
+
+  /// Close the code spans and emphasis descriptions leave open at the end of a paragraph
+  #[clap(long = "sanitize-descriptions", value_parser)]
+  sanitize_descriptions: bool,
+
+  /// Inline the definitions refs point to, keeping only those that recursive refs need
+  #[clap(long, value_parser)]
+  bundle: bool,
+
+  /// What to do when a schema is documented more than once: replace keeps the last block, extend
+  /// keeps them all. Under extend each block becomes a layer of an `allOf`, requiring the
+  /// attributes it requires, while the definition only allows the attributes of all blocks
+  /// (`additionalProperties` is decided once, not per layer).
+  #[clap(long = "on-duplicate", value_parser, default_value = "replace")]
+  on_duplicate: OnDuplicate,
+
+  /// Print how often each function that is only rendered as its arguments is called to stderr,
+  /// as these may deserve handling of their own
+  #[clap(long = "report-unknown-functions", value_parser)]
+  report_unknown_functions: bool,
+
+  /// Print how long reading, parsing, converting and serializing took to stderr, per litfile
+  #[clap(long, value_parser)]
+  profile: bool,
+}
+
+/// What to do with a schema documented again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnDuplicate {
+  /// Keep the last block
+  Replace,
+  /// Keep the attributes of every block, each validating as a layer of an `allOf`
+  Extend,
+}
+
+impl std::str::FromStr for OnDuplicate {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "replace" => Ok(OnDuplicate::Replace),
+      "extend" => Ok(OnDuplicate::Extend),
+      _ => Err(format!("unknown mode {}, expected replace or extend", s)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+  Json,
+  Ts,
+}
+
+impl std::str::FromStr for Emit {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "json" => Ok(Emit::Json),
+      "ts" => Ok(Emit::Ts),
+      _ => Err(format!("unknown output {}, expected json or ts", s)),
+    }
+  }
+}
+
+/// Parses a value of the config file, exiting with 2 if it is invalid
+fn parse_config<T: std::str::FromStr<Err = String>>(
+  name: &str,
+  value: &Option<String>,
+) -> Option<T> {
+  match value.as_deref().map(str::parse) {
+    Some(Err(e)) => {
+      eprintln!("Invalid {} in config: {}", name, e);
+      std::process::exit(2);
+    }
+    Some(Ok(value)) => Some(value),
+    None => None,
+  }
+}
+
+fn parse_version(version: &str) -> Result<semver::Version, String> {
+  convert::parse_version(version).map_err(|e| e.to_string())
 }
 
 pub fn main() {
   env_logger::init();
 
-  let args = Args::parse();
+  let mut args = Args::parse();
+
+  let config = match config::read_config(args.config.as_deref()) {
+    Ok(config) => config,
+    Err(e) => {
+      eprintln!("Unable to read config {}", e);
+      std::process::exit(2);
+    }
+  };
+  // Flags take precedence over the config file
+  if args.litfiles.is_empty() {
+    args.litfiles = config.litfiles.clone();
+  }
+  let schema_path = args
+    .schema
+    .clone()
+    .or(config.schema.clone())
+    .unwrap_or_else(|| "schema.json".to_string());
+  let draft = args
+    .draft
+    .or_else(|| parse_config("draft", &config.draft))
+    .or(args.target.as_ref().map(|target| target.draft))
+    .unwrap_or(Draft::Draft04);
+  let root_additional_properties = args
+    .root_additional_properties
+    .or(
+      args
+        .target
+        .as_ref()
+        .map(|target| target.root_additional_properties),
+    )
+    .unwrap_or(true);
+  let emit = args
+    .emit
+    .or_else(|| parse_config("format", &config.format))
+    .unwrap_or(Emit::Json);
+  let indent = args.indent.or(config.indent);
+
+  if !args.emit_vscode_config.is_empty() {
+    if args.dump_model || emit == Emit::Ts {
+      eprintln!("--emit-vscode-config needs a JSON schema, not a model or TypeScript");
+      std::process::exit(2);
+    }
+    if args.output.is_none() && args.split_by_file.is_none() {
+      eprintln!("--emit-vscode-config needs --output, a schema printed to stdout has no path");
+      std::process::exit(2);
+    }
+  }
+
+  if let Err(e) = Regex::new(&args.var_name_pattern) {
+    eprintln!("Invalid --var-name-pattern: {}", e);
+    std::process::exit(2);
+  }
+
+  if (args.embed_source || args.comment_notes) && draft < Draft::Draft07 {
+    eprintln!("warning: $comment needs draft-07 or later, leaving out the attribute comments");
+  }
+
+  let mut renderer = convert::markdown::Renderer::default();
+  renderer.preserve_unknown_fns = args.preserve_unknown_fns;
+  renderer.decode_entities = args.decode_entities;
 
-  let schema_docs = args
-    .litfiles
+  let convert_opts = convert::Options {
+    renderer,
+    keep_dotted: args.keep_dotted.clone(),
+    since: args.since.clone(),
+    strict: args.strict,
+    embed_source: args.embed_source,
+    prefix_by_file: args.prefix_by_file,
+    max_depth: args.max_depth,
+    comment_notes: args.comment_notes,
+  };
+
+  let mut errors: Vec<String> = vec![];
+  let mut unknown_functions = BTreeMap::new();
+  // Time spent reading, parsing and converting each litfile
+  let mut timings: Vec<(String, [Duration; 3])> = vec![];
+
+  // A model replaces the litfiles, including those of the config file
+  let litfiles = if args.from_model.is_some() {
+    vec![]
+  } else {
+    args.litfiles.clone()
+  };
+
+  let mut schema_docs = litfiles
     .iter()
-    .flat_map(|path| {
-      let contents = fs::read_to_string(path).unwrap();
-      let lit_document = lit::parse(&contents);
-      match lit_document {
-        Ok(doc) => convert::to_jsonschemas(&doc),
+    .flat_map(|litfile| {
+      let path = &fetch::display_name(litfile);
+      let mut timing = [Duration::ZERO; 3];
+
+      let start = Instant::now();
+      let contents = fetch::read_litfile(litfile, args.offline);
+      timing[0] = start.elapsed();
+      let contents = match contents {
+        Ok(contents) => contents,
+        Err(e) => {
+          errors.push(format!("In {}\n{}", path, e));
+          timings.push((path.clone(), timing));
+          return vec![];
+        }
+      };
+
+      let start = Instant::now();
+      let lit_document =
+        lit::parse_with_max_depth(&contents, args.max_depth.unwrap_or(lit::DEFAULT_MAX_DEPTH));
+      timing[1] = start.elapsed();
+
+      if let (true, Ok(doc)) = (args.report_unknown_functions, &lit_document) {
+        convert::count_unknown_functions(doc, &convert_opts, &mut unknown_functions);
+      }
+
+      let start = Instant::now();
+      let schemas = lit_document
+        .as_ref()
+        .map(|doc| convert::to_jsonschemas(doc, path, &contents, &convert_opts));
+      timing[2] = start.elapsed();
+      timings.push((path.clone(), timing));
+
+      match schemas {
+        Ok(Ok(schemas)) => schemas,
+        Ok(Err(convert_errors)) => {
+          errors.extend(convert_errors.iter().map(|e| format!("In {}\n{}", path, e)));
+          vec![]
+        }
+
+        Err(e) => {
+          errors.push(format!("In {}\n{}", path, e));
+          vec![]
+        }
+      }
+    })
+    .fold(HashMap::new(), |mut schema_docs, schema| {
+      insert_schema(&mut schema_docs, schema, args.on_duplicate);
+      schema_docs
+    });
+
+  if args.report_unknown_functions {
+    let mut unknown_functions = unknown_functions.into_iter().collect::<Vec<_>>();
+    unknown_functions.sort_by_key(|(name, count)| (std::cmp::Reverse(*count), name.clone()));
+    for (name, count) in unknown_functions {
+      eprintln!("\\{} {}", name, count);
+    }
+  }
+
+  if let Some(model_path) = &args.from_model {
+    schema_docs = match read_model(model_path) {
+      Ok(model) => model,
+      Err(e) => {
+        eprintln!("Unable to read model {}: {}", model_path, e);
+        std::process::exit(2);
+      }
+    };
+    // A model may have been edited by hand, unlike definitions converted from litfiles
+    errors.extend(
+      schema::serialize::model_problems(&schema_docs)
+        .into_iter()
+        .map(|e| format!("In {}\n{}", model_path, e)),
+    );
+  }
+
+  let orphans = args
+    .orphans
+    .as_ref()
+    .map(|_| schema::orphans::orphans(&schema_docs));
+  if args.drop_orphans {
+    schema_docs.retain(|name, _| !name.starts_with(schema::types::ORPHAN_PREFIX));
+  }
+
+  if !args.only.is_empty() {
+    for name in &args.only {
+      if !schema_docs.contains_key(name) {
+        errors.push(format!("--only {}: no such schema", name));
+      }
+    }
+    let reachable = schema::serialize::reachable_definitions(&schema_docs, &args.only)
+      .into_iter()
+      .cloned()
+      .collect::<HashSet<_>>();
+    schema_docs.retain(|name, _| reachable.contains(name));
+  }
+
+  // Definitions of an external schema are referred to in that file, unless documented here too
+  let mut external_refs = HashMap::new();
+  for external_path in &args.external_defs {
+    let external = read_schema_file(external_path);
+    for (name, pointer) in external_definitions(external_path, &external) {
+      if !schema_docs.contains_key(&name) {
+        external_refs.insert(name, pointer);
+      }
+    }
+  }
+  if let Some(ref_base) = &args.ref_base {
+    let names = if args.ref_base_names.is_empty() {
+      schema::serialize::GUARANTEED_DEFINITIONS
+        .map(String::from)
+        .to_vec()
+    } else {
+      args.ref_base_names.clone()
+    };
+    // The layout of the shared schema isn't ours, so don't assume it follows `--draft`
+    for name in names {
+      external_refs.insert(name, format!("{}#/definitions", ref_base));
+    }
+  }
 
+  let mut type_map = schema::serialize::builtin_type_map();
+  if let Some(target) = &args.target {
+    type_map.extend(target.type_map.clone());
+  }
+  if let Some(type_map_path) = &args.type_map {
+    match read_type_map(type_map_path) {
+      Ok(overrides) => type_map.extend(overrides),
+      Err(e) => {
+        eprintln!("Unable to read type map {}: {}", type_map_path, e);
+        std::process::exit(2);
+      }
+    }
+  }
+
+  let primitives = match &args.primitives {
+    Some(primitives_path) => match read_type_map(primitives_path) {
+      Ok(primitives) => primitives,
+      Err(e) => {
+        eprintln!("Unable to read primitives {}: {}", primitives_path, e);
+        std::process::exit(2);
+      }
+    },
+    None => HashMap::new(),
+  };
+
+  let mut title_overrides = schema::serialize::builtin_title_overrides();
+  if let Some(title_override_path) = &args.title_override {
+    match read_title_overrides(title_override_path) {
+      Ok(overrides) => title_overrides.extend(overrides),
+      Err(e) => {
+        eprintln!(
+          "Unable to read title overrides {}: {}",
+          title_override_path, e
+        );
+        std::process::exit(2);
+      }
+    }
+  }
+
+  let seed_definitions = match &args.seed_schema {
+    Some(seed_path) => {
+      let seed = read_schema_file(seed_path);
+      definitions_of(&seed)
+        .map(|(_, definitions)| definitions.clone().into_iter().collect())
+        .unwrap_or_default()
+    }
+    None => HashMap::new(),
+  };
+
+  let serialize_opts = schema::serialize::Options {
+    draft,
+    seed_definitions,
+    definitions_key: args.definitions_key.clone(),
+    var_name_pattern: args.var_name_pattern.clone(),
+    root_additional_properties,
+    type_map,
+    fragment: args.fragment,
+    enum_case: args.enum_case,
+    drop_deprecated: args.drop_deprecated,
+    emit_property_names: args.emit_property_names,
+    external_refs,
+    indent,
+    title_overrides,
+    primitives,
+    auto_enum_examples: args.auto_enum_examples,
+    enum_as_oneof: args.enum_as_oneof,
+  };
+
+  errors.extend(schema_docs.values().filter_map(|schema| {
+    schema
+      .extends
+      .as_ref()
+      .filter(|base| {
+        !schema_docs.contains_key(*base) && !serialize_opts.external_refs.contains_key(*base)
+      })
+      .map(|base| {
+        format!(
+          "Schema {} extends unknown schema {}",
+          schema.schema_name, base
+        )
+      })
+  }));
+
+  // Litfiles that failed to convert already explain why definitions are missing
+  if !args.fragment && errors.is_empty() {
+    report_problems(
+      schema::serialize::root_problems(&schema_docs),
+      args.strict,
+      &mut errors,
+    );
+  }
+  report_problems(
+    schema::serialize::dangling_refs(&schema_docs, &serialize_opts),
+    args.strict,
+    &mut errors,
+  );
+  if args.validate_examples {
+    // Asking for validation asks for failing examples to fail the run
+    errors.extend(schema::serialize::example_problems(
+      &schema_docs,
+      &serialize_opts,
+    ));
+  }
+  if args.warn_missing_docs {
+    report_problems(
+      schema::coverage::missing_docs(&schema_docs),
+      args.strict,
+      &mut errors,
+    );
+  }
+
+  if !errors.is_empty() {
+    report_errors(&errors, args.max_errors);
+    std::process::exit(if args.check { 2 } else { 1 });
+  }
+
+  if args.stats {
+    eprintln!("{}", schema::stats::stats(&schema_docs));
+  }
+
+  if let Some(coverage_path) = &args.coverage {
+    let coverage = schema::coverage::coverage(&schema_docs);
+    if let Err(e) = fs::write(coverage_path, coverage.to_string()) {
+      eprintln!("Unable to write coverage {}: {}", coverage_path, e);
+      std::process::exit(1);
+    }
+  }
+
+  if let Some(glossary_path) = &args.glossary {
+    let glossary = schema::glossary::glossary(&schema_docs, &serialize_opts);
+    if let Err(e) = fs::write(glossary_path, glossary.to_string()) {
+      eprintln!("Unable to write glossary {}: {}", glossary_path, e);
+      std::process::exit(1);
+    }
+  }
+
+  if let (Some(orphans_path), Some(orphans)) = (&args.orphans, &orphans) {
+    if let Err(e) = fs::write(orphans_path, orphans.to_string()) {
+      eprintln!("Unable to write orphans {}: {}", orphans_path, e);
+      std::process::exit(1);
+    }
+  }
+
+  if let Some(index_path) = &args.index {
+    let index = schema_docs
+      .iter()
+      .map(|(name, schema)| {
+        (
+          name,
+          json!({"file": schema.source.file, "line": schema.source.line}),
+        )
+      })
+      .collect::<HashMap<_, _>>();
+    if let Err(e) = fs::write(index_path, json!(index).to_string()) {
+      eprintln!("Unable to write index {}: {}", index_path, e);
+      std::process::exit(1);
+    }
+  }
+
+  if args.dump_model {
+    let start = Instant::now();
+    write_output(&args.output, |writer| {
+      writeln!(writer, "{}", dump_model(&schema_docs))
+    });
+    if args.profile {
+      report_profile(&timings, start.elapsed());
+    }
+    return;
+  }
+
+  if emit == Emit::Ts {
+    let start = Instant::now();
+    write_output(&args.output, |writer| {
+      write!(
+        writer,
+        "{}",
+        schema::typescript::typescript(&schema_docs, &serialize_opts)
+      )
+    });
+    if args.profile {
+      report_profile(&timings, start.elapsed());
+    }
+    return;
+  }
+
+  let post_process = |document: &mut serde_json::Value| {
+    if args.dedup_definitions {
+      schema::serialize::dedup_definitions(document, &serialize_opts);
+    }
+    if args.minify_descriptions {
+      schema::serialize::minify_descriptions(document);
+    }
+    if let Some(max) = args.max_description {
+      schema::serialize::truncate_descriptions(document, max);
+    }
+    if args.sanitize_descriptions {
+      schema::serialize::sanitize_descriptions(document);
+    }
+    if args.bundle {
+      schema::serialize::bundle(document, &serialize_opts);
+    }
+  };
+
+  if !args.emit_vscode_config.is_empty() {
+    let associated = match (&args.split_by_file, &args.output) {
+      (Some(dir), _) => match split_file_name(&schema_docs, "pipeline") {
+        Ok(Some(file_name)) => Path::new(dir).join(file_name).display().to_string(),
+        Ok(None) => {
+          eprintln!("--emit-vscode-config needs a pipeline definition for the split schemas");
+          std::process::exit(2);
+        }
         Err(e) => {
-          eprintln!("In {}", path);
-          eprintln!("{}", e);
-          panic!("Unexpected parse error, aborting");
+          eprintln!("Unable to write schemas to {}: {}", dir, e);
+          std::process::exit(1);
         }
+      },
+      (None, output) => output.clone().unwrap_or_default(),
+    };
+    println!(
+      "{:#}",
+      vscode_settings(&associated, &args.emit_vscode_config)
+    );
+  }
+
+  if let Some(dir) = &args.split_by_file {
+    let start = Instant::now();
+    let written = write_split_schemas(&schema_docs, Path::new(dir), &serialize_opts, &post_process);
+    if let Err(e) = written {
+      eprintln!("Unable to write schemas to {}: {}", dir, e);
+      std::process::exit(1);
+    }
+    if args.profile {
+      report_profile(&timings, start.elapsed());
+    }
+    return;
+  }
+
+  let start = Instant::now();
+  let mut generated = build_document(&schema_docs, &serialize_opts, Some(&post_process));
+  if let (Some(output), false) = (&args.output, args.no_merge) {
+    merge_existing(&mut generated, output, &serialize_opts);
+  }
+  if args.profile && (args.check || args.diff.is_some()) {
+    report_profile(&timings, start.elapsed());
+  }
+
+  if args.check {
+    let target = args.output.as_ref().unwrap_or(&schema_path);
+    let existing = match fs::read(target) {
+      Ok(existing) => existing,
+      Err(e) => {
+        eprintln!("Unable to read {}: {}", target, e);
+        std::process::exit(2);
       }
+    };
+    if existing == schema_bytes(&generated, &serialize_opts) {
+      return;
+    }
+
+    eprintln!("{} is out of date", target);
+    // A file differing only in formatting has no changed definitions to list
+    if let Ok(existing) = serde_json::from_slice(&existing) {
+      for name in schema::diff::changed_definitions(&existing, &generated) {
+        println!("{}", name);
+      }
+    }
+    std::process::exit(1);
+  }
+
+  if let Some(diff_path) = &args.diff {
+    let existing = read_schema_file(diff_path);
+
+    let changes = schema::diff::diff(&existing, &generated);
+    for change in &changes {
+      println!("{}", change);
+    }
+    if !changes.is_empty() {
+      std::process::exit(1);
+    }
+    return;
+  }
+
+  // Written as it is serialized, as the full schema is large
+  write_output(&args.output, |writer| {
+    write_schema(&generated, writer, &serialize_opts)
+  });
+  if args.profile {
+    report_profile(&timings, start.elapsed());
+  }
+}
+
+/// The VS Code `yaml.schemas` setting associating files matching the globs with the schema
+fn vscode_settings(schema_path: &str, globs: &[String]) -> serde_json::Value {
+  json!({ "yaml.schemas": { schema_path: globs } })
+}
+
+/// The file each source file's schemas are written to by `write_split_schemas`, named after it
+fn split_file_names(
+  schema_docs: &HashMap<String, schema::types::Schema>,
+) -> Result<HashMap<&String, String>, String> {
+  let mut file_names: HashMap<&String, String> = HashMap::new();
+  for source_file in schema_docs.values().map(|s| &s.source.file) {
+    if file_names.contains_key(source_file) {
+      continue;
+    }
+    let stem = Path::new(source_file)
+      .file_stem()
+      .map(|stem| stem.to_string_lossy().to_string())
+      .unwrap_or_default();
+    let file_name = format!("{}.json", stem);
+    if let Some((other, _)) = file_names.iter().find(|(_, name)| **name == file_name) {
+      return Err(format!(
+        "{} and {} would both be written to {}",
+        other, source_file, file_name
+      ));
+    }
+    file_names.insert(source_file, file_name);
+  }
+  Ok(file_names)
+}
+
+/// The file `write_split_schemas` writes the definition to, if there is such a definition
+fn split_file_name(
+  schema_docs: &HashMap<String, schema::types::Schema>,
+  name: &str,
+) -> Result<Option<String>, String> {
+  let mut file_names = split_file_names(schema_docs)?;
+  Ok(
+    schema_docs
+      .get(name)
+      .and_then(|schema| file_names.remove(&schema.source.file)),
+  )
+}
+
+/// Writes one schema per source file, named after the file. Refs to definitions from other
+/// files point into the schema file for that source.
+fn write_split_schemas(
+  schema_docs: &HashMap<String, schema::types::Schema>,
+  dir: &Path,
+  opts: &schema::serialize::Options,
+  post_process: &dyn Fn(&mut serde_json::Value),
+) -> Result<(), String> {
+  let file_names = split_file_names(schema_docs)?;
+
+  fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+  for (source_file, file_name) in &file_names {
+    let file_docs = schema_docs
+      .iter()
+      .filter(|(_, schema)| &schema.source.file == *source_file)
+      .map(|(name, schema)| (name.clone(), schema.clone()))
+      .collect::<HashMap<_, _>>();
+
+    let mut external_refs = opts.external_refs.clone();
+    external_refs.extend(
+      schema_docs
+        .iter()
+        .filter(|(_, schema)| &schema.source.file != *source_file)
+        .map(|(name, schema)| {
+          let file_name = &file_names[&schema.source.file];
+          (
+            name.clone(),
+            format!("{}#/{}", file_name, opts.definitions_key()),
+          )
+        }),
+    );
+
+    let file_opts = schema::serialize::Options {
+      external_refs,
+      ..opts.clone()
+    };
+
+    let document = build_document(&file_docs, &file_opts, Some(post_process));
+    let file = fs::File::create(dir.join(file_name)).map_err(|e| e.to_string())?;
+    write_schema(&document, io::BufWriter::new(file), &file_opts).map_err(|e| e.to_string())?;
+  }
+
+  Ok(())
+}
+
+/// The bytes `write_schema` writes for the document
+fn schema_bytes(document: &serde_json::Value, opts: &schema::serialize::Options) -> Vec<u8> {
+  let mut written = vec![];
+  // Writing to memory can't fail, nor can serializing a Value
+  write_schema(document, &mut written, opts).unwrap();
+  written
+}
+
+/// Writes to `output`, or to stdout without it, exiting with 1 if that fails
+fn write_output(output: &Option<String>, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+  let written = match output {
+    Some(path) => fs::File::create(path).and_then(|file| {
+      let mut writer = io::BufWriter::new(file);
+      write(&mut writer)?;
+      writer.flush()
+    }),
+    None => {
+      let mut writer = io::BufWriter::new(io::stdout().lock());
+      write(&mut writer).and_then(|_| writer.flush())
+    }
+  };
+  if let Err(e) = written {
+    let output = output.as_deref().unwrap_or("the output");
+    eprintln!("Unable to write {}: {}", output, e);
+    std::process::exit(1);
+  }
+}
+
+/// Adds the definitions of the schema already at `path` that `document` lacks, keeping those
+/// written by hand. A schema that doesn't exist yet has nothing to add.
+fn merge_existing(document: &mut serde_json::Value, path: &str, opts: &schema::serialize::Options) {
+  if !Path::new(path).exists() {
+    return;
+  }
+  let existing = read_schema_file(path);
+  let existing = if opts.fragment {
+    existing.as_object()
+  } else {
+    existing
+      .get(opts.definitions_key())
+      .and_then(|definitions| definitions.as_object())
+      .or_else(|| definitions_of(&existing).map(|(_, definitions)| definitions))
+  };
+  let generated = if opts.fragment {
+    Some(&*document)
+  } else {
+    document.get(opts.definitions_key())
+  };
+  let kept = existing
+    .into_iter()
+    .flatten()
+    .filter(|(name, _)| {
+      generated
+        .and_then(|generated| generated.get(name))
+        .is_none()
     })
-    .map(|schema| (schema.schema_name.clone(), schema))
-    .collect::<HashMap<_, _>>();
+    .map(|(name, definition)| (name.clone(), definition.clone()))
+    .collect::<Vec<_>>();
+  if kept.is_empty() {
+    return;
+  }
+
+  let definitions = if opts.fragment {
+    document
+  } else {
+    // `--bundle` may have left no definitions at all
+    &mut document[opts.definitions_key()]
+  };
+  if definitions.is_null() {
+    *definitions = json!({});
+  }
+  if let Some(definitions) = definitions.as_object_mut() {
+    for (name, definition) in kept {
+      log::debug!("Keeping {} from {}", name, path);
+      definitions.insert(name, definition);
+    }
+  }
+}
+
+/// Writes a schema document as `opts` asks, ending it with a single newline
+fn write_schema<W: Write>(
+  document: &serde_json::Value,
+  mut writer: W,
+  opts: &schema::serialize::Options,
+) -> io::Result<()> {
+  schema::serialize::write_json(document, &mut writer, opts)?;
+  writeln!(writer)?;
+  writer.flush()
+}
+
+/// Reads an existing schema, exiting with 2 if it can't
+fn read_schema_file(path: &str) -> serde_json::Value {
+  let existing = fs::read_to_string(path)
+    .map_err(|e| e.to_string())
+    .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()));
+  match existing {
+    Ok(existing) => existing,
+    Err(e) => {
+      eprintln!("Unable to read {}: {}", path, e);
+      std::process::exit(2);
+    }
+  }
+}
+
+/// The definitions of an existing schema and the key it keeps them under
+fn definitions_of(
+  schema: &serde_json::Value,
+) -> Option<(&'static str, &serde_json::Map<String, serde_json::Value>)> {
+  ["definitions", "$defs"].into_iter().find_map(|key| {
+    schema
+      .get(key)
+      .and_then(|definitions| definitions.as_object())
+      .map(|definitions| (key, definitions))
+  })
+}
+
+/// The definitions of the external schema at `path`, with the pointer to where they are in it
+fn external_definitions(path: &str, external: &serde_json::Value) -> Vec<(String, String)> {
+  match definitions_of(external) {
+    Some((key, definitions)) => definitions
+      .keys()
+      .map(|name| (name.clone(), format!("{}#/{}", path, key)))
+      .collect(),
+    None => vec![],
+  }
+}
+
+/// The definitions as `--dump-model` writes them. Going through a Value sorts the keys, so dumps of
+/// the same model are identical.
+fn dump_model(schema_docs: &HashMap<String, schema::types::Schema>) -> serde_json::Value {
+  serde_json::to_value(schema_docs).unwrap()
+}
+
+/// Reads a model written by `--dump-model`, from stdin if the path is `-`
+fn read_model(path: &str) -> Result<HashMap<String, schema::types::Schema>, String> {
+  let contents = if path == "-" {
+    std::io::read_to_string(std::io::stdin()).map_err(|e| e.to_string())?
+  } else {
+    fs::read_to_string(path).map_err(|e| e.to_string())?
+  };
+  serde_json::from_str(&contents).map_err(|e| e.to_string())
+}
+
+fn read_type_map(path: &str) -> Result<HashMap<String, serde_json::Value>, String> {
+  let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+  serde_json::from_str(&contents).map_err(|e| e.to_string())
+}
+
+fn read_title_overrides(path: &str) -> Result<HashMap<String, String>, String> {
+  let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+  serde_json::from_str(&contents).map_err(|e| e.to_string())
+}
+
+fn insert_schema(
+  schema_docs: &mut HashMap<String, schema::types::Schema>,
+  schema: schema::types::Schema,
+  on_duplicate: OnDuplicate,
+) {
+  if schema.schema_name.starts_with(schema::types::ORPHAN_PREFIX) {
+    if let Some(existing) = schema_docs.get(&schema.schema_name) {
+      if existing.properties != schema.properties {
+        log::warn!(
+          "{} is documented differently in {}:{} and {}:{}, keeping the former",
+          schema.schema_name,
+          existing.source.file,
+          existing.source.line,
+          schema.source.file,
+          schema.source.line
+        );
+      }
+      return;
+    }
+  }
+
+  if let (OnDuplicate::Extend, Some(existing)) =
+    (on_duplicate, schema_docs.get_mut(&schema.schema_name))
+  {
+    log::debug!(
+      "Extending {} with {}:{}",
+      schema.schema_name,
+      schema.source.file,
+      schema.source.line
+    );
+    if existing.layers.is_empty() {
+      existing.layers.push(existing.properties.clone());
+    }
+    if !schema.properties.is_empty() {
+      existing.layers.push(schema.properties.clone());
+    }
+    for (name, prop) in schema.properties {
+      existing.properties.entry(name).or_insert(prop);
+    }
+    for member in schema.group_members {
+      if !existing.group_members.contains(&member) {
+        existing.group_members.push(member);
+      }
+    }
+    if existing.docs.is_empty() {
+      existing.docs = schema.docs;
+    }
+    return;
+  }
+
+  schema_docs.insert(schema.schema_name.clone(), schema);
+}
+
+/// Prints the time each stage took per litfile, their totals and the time spent serializing
+fn report_profile(timings: &[(String, [Duration; 3])], serialize: Duration) {
+  let width = timings
+    .iter()
+    .map(|(path, _)| path.len())
+    .chain(["serialize".len()])
+    .max()
+    .unwrap_or(0);
+  let millis = |duration: &Duration| format!("{:.2}ms", duration.as_secs_f64() * 1000.0);
+  let row = |name: &str, timing: &[Duration; 3]| {
+    eprintln!(
+      "{:width$}  {:>10}  {:>10}  {:>10}",
+      name,
+      millis(&timing[0]),
+      millis(&timing[1]),
+      millis(&timing[2]),
+      width = width
+    )
+  };
+
+  eprintln!(
+    "{:width$}  {:>10}  {:>10}  {:>10}",
+    "file",
+    "read",
+    "parse",
+    "convert",
+    width = width
+  );
+  let mut total = [Duration::ZERO; 3];
+  for (path, timing) in timings {
+    row(path, timing);
+    for (sum, stage) in total.iter_mut().zip(timing) {
+      *sum += *stage;
+    }
+  }
+  row("total", &total);
+  eprintln!(
+    "{:width$}  {:>10}",
+    "serialize",
+    millis(&serialize),
+    width = width
+  );
+}
+
+/// Prints problems of the schema as warnings to stderr, or turns them into errors with `--strict`
+fn report_problems(problems: Vec<String>, strict: bool, errors: &mut Vec<String>) {
+  for problem in problems {
+    if strict {
+      errors.push(problem);
+    } else {
+      eprintln!("warning: {}", problem);
+    }
+  }
+}
+
+fn report_errors(errors: &[String], max_errors: usize) {
+  for error in errors.iter().take(max_errors) {
+    eprintln!("{}", error);
+  }
+
+  if errors.len() > max_errors {
+    eprintln!("...and {} more", errors.len() - max_errors);
+  }
+
+  eprintln!("{} error(s), aborting", errors.len());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use itertools::Itertools;
+  use schema::types::Schema;
+
+  fn schema_docs(files: &[(&str, &str)], on_duplicate: OnDuplicate) -> HashMap<String, Schema> {
+    let mut schema_docs = HashMap::new();
+    for (path, contents) in files {
+      let doc = lit::parse(contents).unwrap();
+      for schema in convert::to_jsonschemas(&doc, path, contents, &Default::default()).unwrap() {
+        insert_schema(&mut schema_docs, schema, on_duplicate);
+      }
+    }
+    schema_docs
+  }
+
+  const REOPENED: [(&str, &str); 2] = [
+    (
+      "a.lit",
+      r"\schema{pipeline}{\required-attribute{a}{string}{A.}}",
+    ),
+    (
+      "b.lit",
+      r"\schema{pipeline}{\required-attribute{b}{number}{B.}}",
+    ),
+  ];
+
+  #[test]
+  fn reopened_schemas_are_layered_under_extend() {
+    let docs = schema_docs(&REOPENED, OnDuplicate::Extend);
+    let schema = schema::serialize::build_schema(&docs, &Default::default());
+    let pipeline = &schema["definitions"]["pipeline"];
+
+    assert_eq!(pipeline["additionalProperties"], false);
+    assert_eq!(pipeline["properties"], json!({ "a": {}, "b": {} }));
+    let layers = pipeline["allOf"].as_array().unwrap();
+    assert_eq!(layers.len(), 2);
+    assert_eq!(layers[0]["required"], json!(["a"]));
+    assert_eq!(layers[0]["properties"]["a"]["description"], "A.");
+    assert_eq!(layers[1]["required"], json!(["b"]));
+    assert_eq!(layers[1]["properties"]["b"]["description"], "B.");
+  }
+
+  #[test]
+  fn reopened_schemas_replace_each_other_by_default() {
+    let docs = schema_docs(&REOPENED, OnDuplicate::Replace);
+    let pipeline = &docs["pipeline"];
+    assert!(pipeline.layers.is_empty());
+    assert_eq!(pipeline.properties.keys().collect_vec(), ["b"]);
+  }
+
+  #[test]
+  fn external_refs_point_into_the_definitions_key_of_their_file() {
+    let docs = schema_docs(
+      &[(
+        "a.lit",
+        r"\schema{pipeline}{\optional-attribute{a}{foo}{A.}}",
+      )],
+      OnDuplicate::Replace,
+    );
+    let external = json!({ "definitions": { "foo": { "type": "string" } } });
+    let opts = schema::serialize::Options {
+      draft: Draft::Draft202012,
+      external_refs: external_definitions("ext.json", &external)
+        .into_iter()
+        .collect(),
+      ..Default::default()
+    };
+    let schema = schema::serialize::build_schema(&docs, &opts);
+    assert_eq!(
+      schema["$defs"]["pipeline"]["properties"]["a"]["$ref"],
+      "ext.json#/definitions/foo"
+    );
+    assert!(schema["$defs"].get("foo").is_none());
+  }
+
+  #[test]
+  fn dumped_models_serialize_to_the_same_schema() {
+    let docs = schema_docs(
+      &[(
+        "a.lit",
+        r#"\schema{pipeline}{
+          {- @x: {"x-order": 1} -}
+          \required-attribute{jobs}{[job]}{The jobs.}
+          \optional-attribute{mode}{`fast` | `slow`}{\definitions{
+            \definition{`fast`}{Quick.}
+          }}
+          \optional-attribute{retry}{config}{Retries.
+            \optional-attribute{attempts}{number}{How often. \example-toggle{Three}{3}}
+          }
+        }
+        \schema-group{step}{step}{
+          \schema{get}{\required-attribute{get}{string}{G.}}
+          \schema{put}{\required-attribute{put}{string}{P.}}
+        }
+        \schema{job}{\required-attribute{plan}{[step]}{Steps.}}"#,
+      )],
+      OnDuplicate::Replace,
+    );
+    assert!(docs.contains_key("get"));
+    assert_eq!(docs["pipeline"].properties["retry"].attributes.len(), 1);
+    let model: HashMap<String, Schema> =
+      serde_json::from_str(&dump_model(&docs).to_string()).unwrap();
+    assert_eq!(model, docs);
+    for draft in [Draft::Draft04, Draft::Draft202012] {
+      let opts = schema::serialize::Options {
+        draft,
+        ..Default::default()
+      };
+      assert_eq!(
+        build_document(&model, &opts, None),
+        build_document(&docs, &opts, None)
+      );
+    }
+  }
+
+  #[test]
+  fn edited_models_are_checked_before_serializing() {
+    let mut docs = schema_docs(
+      &[(
+        "a.lit",
+        r"\schema{pipeline}{\optional-attribute{a}{string}{A.}}",
+      )],
+      OnDuplicate::Replace,
+    );
+    let pipeline = docs.get_mut("pipeline").unwrap();
+    pipeline
+      .extensions
+      .insert("x-broken".to_string(), "{".to_string());
+    let a = pipeline.properties.get_mut("a").unwrap();
+    a.type_name =
+      schema::types::PropertyType::ArrayOf(Box::new(schema::types::PropertyType::OneOf(vec![])));
+    assert_eq!(
+      schema::serialize::model_problems(&docs),
+      [
+        "In schema pipeline: extension x-broken isn't JSON: EOF while parsing an object at line 1 \
+         column 1",
+        "In schema pipeline, attribute a: type has a union without alternatives",
+      ]
+    );
+  }
+
+  #[test]
+  fn existing_output_keeps_the_definitions_the_docs_lack() {
+    let docs = schema_docs(
+      &[(
+        "a.lit",
+        r"\schema{pipeline}{\optional-attribute{a}{string}{A.}}",
+      )],
+      OnDuplicate::Replace,
+    );
+    let path = std::env::temp_dir().join(format!("merge-existing-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let opts = schema::serialize::Options::default();
+
+    // Nothing to keep before the first run
+    let mut document = build_document(&docs, &opts, None);
+    merge_existing(&mut document, path, &opts);
+    assert_eq!(document, build_document(&docs, &opts, None));
+
+    let existing = json!({
+      "definitions": {
+        "pipeline": { "stale": true },
+        "hand_written": { "type": "string" }
+      }
+    });
+    fs::write(path, existing.to_string()).unwrap();
+    merge_existing(&mut document, path, &opts);
+    // A fragment is the definitions map itself
+    let fragment_opts = schema::serialize::Options {
+      fragment: true,
+      ..Default::default()
+    };
+    fs::write(path, existing["definitions"].to_string()).unwrap();
+    let mut fragment = build_document(&docs, &fragment_opts, None);
+    merge_existing(&mut fragment, path, &fragment_opts);
+    fs::remove_file(path).unwrap();
+
+    let definitions = &document["definitions"];
+    assert_eq!(definitions["hand_written"], json!({ "type": "string" }));
+    assert!(definitions["pipeline"].get("stale").is_none());
+    assert_eq!(
+      definitions["pipeline"]["properties"]["a"]["description"],
+      "A."
+    );
+    assert_eq!(fragment["hand_written"], json!({ "type": "string" }));
+    assert!(fragment["pipeline"].get("stale").is_none());
+  }
+
+  #[test]
+  fn checks_compare_the_bytes_that_would_be_written() {
+    let docs = schema_docs(
+      &[(
+        "a.lit",
+        r"\schema{pipeline}{\optional-attribute{a}{string}{A.}}",
+      )],
+      OnDuplicate::Replace,
+    );
+    let opts = schema::serialize::Options::default();
+    let document = build_document(&docs, &opts, None);
+
+    let mut written = vec![];
+    write_schema(&document, &mut written, &opts).unwrap();
+    assert_eq!(schema_bytes(&document, &opts), written);
+    // The same schema formatted differently would be rewritten
+    let indented = schema::serialize::Options {
+      indent: Some(2),
+      ..opts.clone()
+    };
+    assert_ne!(schema_bytes(&document, &indented), written);
+    assert_ne!(
+      serde_json::to_vec(&document).unwrap(),
+      written,
+      "written schemas end with a newline"
+    );
+  }
 
-  let schema = schema::serialize::serialize(&schema_docs);
+  #[test]
+  fn vscode_settings_associate_the_written_schema() {
+    let globs = ["ci/*.yml".to_string(), "pipelines/**/*.yml".to_string()];
+    assert_eq!(
+      vscode_settings("out/pipeline.json", &globs),
+      json!({ "yaml.schemas": { "out/pipeline.json": ["ci/*.yml", "pipelines/**/*.yml"] } })
+    );
 
-  println!("{}", schema);
+    // Split schemas associate the file holding the pipeline
+    let docs = schema_docs(
+      &[
+        (
+          "docs/jobs.lit",
+          r"\schema{job}{\required-attribute{name}{string}{N.}}",
+        ),
+        (
+          "docs/pipeline.lit",
+          r"\schema{pipeline}{\optional-attribute{jobs}{[job]}{J.}}",
+        ),
+      ],
+      OnDuplicate::Replace,
+    );
+    assert_eq!(
+      split_file_name(&docs, "pipeline").unwrap().as_deref(),
+      Some("pipeline.json")
+    );
+    assert_eq!(split_file_name(&docs, "missing").unwrap(), None);
+  }
 }
@@ -1,2 +1,8 @@
+pub mod coverage;
+pub mod diff;
+pub mod glossary;
+pub mod orphans;
 pub mod serialize;
+pub mod stats;
 pub mod types;
+pub mod typescript;
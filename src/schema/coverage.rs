@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::schema::types::{Property, Schema};
+
+#[derive(Default)]
+struct Counts {
+  attributes: usize,
+  typed: usize,
+  described: usize,
+  with_examples: usize,
+}
+
+impl Counts {
+  fn add(&mut self, prop: &Property) {
+    self.attributes += 1;
+    if prop.coercion.is_none() {
+      self.typed += 1;
+    }
+    if !prop.docs.is_empty() {
+      self.described += 1;
+    }
+    if !prop.examples.is_empty() {
+      self.with_examples += 1;
+    }
+  }
+
+  fn to_json(&self) -> Value {
+    json!({
+      "attributes": self.attributes,
+      "typed": self.typed,
+      "string_fallback": self.attributes - self.typed,
+      "described": self.described,
+      "with_examples": self.with_examples
+    })
+  }
+}
+
+/// How completely each schema's attributes are documented: how many have a type the grammar
+/// understood (rather than a fallback to string), a description or an example. Schema examples
+/// are counted separately.
+pub fn coverage(schema_docs: &HashMap<String, Schema>) -> Value {
+  let mut total = Counts::default();
+  let mut schemas = BTreeMap::new();
+
+  for (name, schema) in schema_docs {
+    let mut counts = Counts::default();
+    for prop in schema.properties.values() {
+      counts.add(prop);
+      total.add(prop);
+    }
+    let mut entry = counts.to_json();
+    entry["examples"] = json!(schema.examples.len());
+    schemas.insert(name, entry);
+  }
+
+  json!({ "schemas": schemas, "total": total.to_json() })
+}
+
+/// The attributes, inline ones included, whose description would be empty, as a worklist sorted
+/// by schema and attribute
+pub fn missing_docs(schema_docs: &HashMap<String, Schema>) -> Vec<String> {
+  fn add(path: &str, prop: &Property, missing: &mut Vec<String>) {
+    if prop.docs.trim().is_empty() {
+      missing.push(path.to_string());
+    }
+    for (name, attribute) in &prop.attributes {
+      add(&format!("{}.{}", path, name), attribute, missing);
+    }
+  }
+
+  schema_docs
+    .values()
+    .sorted_by_key(|schema| &schema.schema_name)
+    .flat_map(|schema| {
+      let mut missing = vec![];
+      for (name, prop) in schema.properties.iter().sorted_by_key(|(name, _)| *name) {
+        add(name, prop, &mut missing);
+      }
+      missing.into_iter().map(move |attribute| {
+        format!(
+          "In schema {}, attribute {}: no description",
+          schema.schema_name, attribute
+        )
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn attributes_with_examples_are_counted_from_their_examples() {
+    let contents = r"\schema{s}{
+      \optional-attribute{a}{number}{A. \example-toggle{One}{1}\example-toggle{Two}{2}}
+      \optional-attribute{b}{number}{B.}
+    }";
+    let doc = crate::lit::parse(contents).unwrap();
+    let schema_docs =
+      crate::convert::to_jsonschemas(&doc, "test.lit", contents, &Default::default())
+        .unwrap()
+        .into_iter()
+        .map(|schema| (schema.schema_name.clone(), schema))
+        .collect();
+    let coverage = coverage(&schema_docs);
+    assert_eq!(coverage["schemas"]["s"]["attributes"], 2);
+    assert_eq!(coverage["schemas"]["s"]["with_examples"], 1);
+  }
+}
@@ -0,0 +1,145 @@
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+use serde_json::{Map, Value};
+
+fn definitions(schema: &Value) -> Map<String, Value> {
+  schema
+    .get("definitions")
+    .or_else(|| schema.get("$defs"))
+    .and_then(Value::as_object)
+    .cloned()
+    .unwrap_or_default()
+}
+
+fn properties(definition: &Value) -> Map<String, Value> {
+  definition
+    .get("properties")
+    .and_then(Value::as_object)
+    .cloned()
+    .unwrap_or_default()
+}
+
+fn required(definition: &Value) -> BTreeSet<&str> {
+  definition
+    .get("required")
+    .and_then(Value::as_array)
+    .map(|names| names.iter().filter_map(Value::as_str).collect())
+    .unwrap_or_default()
+}
+
+/// The property schema without its documentation, so doc changes don't count as type changes
+fn property_shape(property: &Value) -> Value {
+  let mut shape = property.clone();
+  if let Some(object) = shape.as_object_mut() {
+    object.remove("description");
+  }
+  shape
+}
+
+/// Everything but the properties and required list of a definition
+fn definition_rest(definition: &Value) -> Value {
+  let mut rest = definition.clone();
+  if let Some(object) = rest.as_object_mut() {
+    object.remove("properties");
+    object.remove("required");
+    object.remove("description");
+  }
+  rest
+}
+
+/// The names of the definitions that differ in any way between `old` and `new`, including added
+/// and removed ones. Differences outside of the definitions are listed as `(root)`.
+pub fn changed_definitions(old: &Value, new: &Value) -> Vec<String> {
+  let old_defs = definitions(old);
+  let new_defs = definitions(new);
+
+  let root = |schema: &Value| {
+    let mut root = schema.clone();
+    if let Some(object) = root.as_object_mut() {
+      object.remove("definitions");
+      object.remove("$defs");
+    }
+    root
+  };
+
+  let mut changed = old_defs
+    .keys()
+    .chain(new_defs.keys())
+    .sorted()
+    .dedup()
+    .filter(|name| old_defs.get(*name) != new_defs.get(*name))
+    .cloned()
+    .collect_vec();
+
+  if root(old) != root(new) {
+    changed.insert(0, "(root)".to_string());
+  }
+  changed
+}
+
+/// Describes how the definitions of `new` differ from those of `old`, one line per change
+pub fn diff(old: &Value, new: &Value) -> Vec<String> {
+  let old_defs = definitions(old);
+  let new_defs = definitions(new);
+  let mut changes = vec![];
+
+  let names = old_defs.keys().chain(new_defs.keys()).sorted().dedup();
+
+  for name in names {
+    let (old_def, new_def) = match (old_defs.get(name), new_defs.get(name)) {
+      (None, Some(_)) => {
+        changes.push(format!("+ definition {}", name));
+        continue;
+      }
+      (Some(_), None) => {
+        changes.push(format!("- definition {}", name));
+        continue;
+      }
+      (Some(old_def), Some(new_def)) => (old_def, new_def),
+      (None, None) => unreachable!(),
+    };
+
+    if definition_rest(old_def) != definition_rest(new_def) {
+      changes.push(format!("~ definition {} changed", name));
+    }
+
+    let old_props = properties(old_def);
+    let new_props = properties(new_def);
+    let old_required = required(old_def);
+    let new_required = required(new_def);
+
+    let prop_names = old_props.keys().chain(new_props.keys()).sorted().dedup();
+
+    for prop_name in prop_names {
+      match (old_props.get(prop_name), new_props.get(prop_name)) {
+        (None, Some(_)) => changes.push(format!("+ {}.{}", name, prop_name)),
+        (Some(_), None) => changes.push(format!("- {}.{}", name, prop_name)),
+        (Some(old_prop), Some(new_prop)) => {
+          if property_shape(old_prop) != property_shape(new_prop) {
+            changes.push(format!(
+              "~ {}.{} type changed from {} to {}",
+              name,
+              prop_name,
+              property_shape(old_prop),
+              property_shape(new_prop)
+            ));
+          }
+          let was_required = old_required.contains(prop_name.as_str());
+          let is_required = new_required.contains(prop_name.as_str());
+          if was_required != is_required {
+            changes.push(format!(
+              "~ {}.{} is now {}",
+              name,
+              prop_name,
+              if is_required { "required" } else { "optional" }
+            ));
+          }
+        }
+        (None, None) => unreachable!(),
+      }
+    }
+  }
+
+  changes
+}
@@ -0,0 +1,63 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::{json, Value};
+
+use crate::schema::serialize::{constant_to_json, Options};
+use crate::schema::types::{Property, PropertyType, Schema};
+
+/// Every constant attribute types allow, such as the step keywords, mapped to the attributes that
+/// allow it as `schema.attribute`. Values are written as they are emitted, after `--enum-case`.
+pub fn glossary(schema_docs: &HashMap<String, Schema>, opts: &Options) -> Value {
+  let mut glossary: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+  for (name, schema) in schema_docs {
+    for (prop_name, prop) in &schema.properties {
+      add_property(
+        &format!("{}.{}", name, prop_name),
+        prop,
+        opts,
+        &mut glossary,
+      );
+    }
+  }
+  json!(glossary)
+}
+
+fn add_property(
+  path: &str,
+  prop: &Property,
+  opts: &Options,
+  glossary: &mut BTreeMap<String, BTreeSet<String>>,
+) {
+  // Inline attributes replace the documented type
+  if !prop.attributes.is_empty() {
+    for (name, attribute) in &prop.attributes {
+      add_property(&format!("{}.{}", path, name), attribute, opts, glossary);
+    }
+    return;
+  }
+  add_constants(path, &prop.type_name, opts, glossary);
+}
+
+fn add_constants(
+  path: &str,
+  prop_type: &PropertyType,
+  opts: &Options,
+  glossary: &mut BTreeMap<String, BTreeSet<String>>,
+) {
+  match prop_type {
+    PropertyType::Constant(value, _) => {
+      let value = match constant_to_json(value, opts) {
+        Value::String(value) => value,
+        number => number.to_string(),
+      };
+      glossary.entry(value).or_default().insert(path.to_string());
+    }
+    PropertyType::OneOf(types) => types
+      .iter()
+      .for_each(|t| add_constants(path, t, opts, glossary)),
+    PropertyType::ArrayOf(inner) => add_constants(path, inner, opts, glossary),
+    PropertyType::Dict { value, .. } => add_constants(path, value, opts, glossary),
+    // Negated constants are the values an attribute doesn't allow
+    PropertyType::Not(_) | PropertyType::Ref(_) => {}
+  }
+}
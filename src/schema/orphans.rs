@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::schema::types::{PropertyType, Schema, ORPHAN_PREFIX};
+
+/// Every attribute documented outside of any schema, with its type in the notation of the docs,
+/// whether it is required and where it is documented. Sorted by name, then source.
+pub fn orphans(schema_docs: &HashMap<String, Schema>) -> Value {
+  let orphans = schema_docs
+    .values()
+    .filter(|schema| schema.schema_name.starts_with(ORPHAN_PREFIX))
+    .flat_map(|schema| {
+      schema.properties.iter().map(move |(name, prop)| {
+        (
+          name,
+          &schema.source.file,
+          schema.source.line,
+          type_notation(&prop.type_name),
+          prop.required,
+        )
+      })
+    })
+    .sorted()
+    .map(|(name, file, line, type_name, required)| {
+      json!({
+        "name": name,
+        "type": type_name,
+        "required": required,
+        "file": file,
+        "line": line
+      })
+    })
+    .collect_vec();
+  json!(orphans)
+}
+
+/// Writes a type back the way the docs annotate attributes, such as ``[string] | `none` ``
+fn type_notation(prop_type: &PropertyType) -> String {
+  match prop_type {
+    PropertyType::OneOf(types) => types.iter().map(type_notation).join(" | "),
+    PropertyType::Constant(value, _) => format!("`{}`", value),
+    PropertyType::Ref(name) => name.clone(),
+    PropertyType::ArrayOf(inner) => format!("[{}]", type_notation(inner)),
+    PropertyType::Not(inner) => format!("!{}", type_notation(inner)),
+    PropertyType::Dict { key, value } => {
+      format!("{{{}: {}}}", type_notation(key), type_notation(value))
+    }
+  }
+}
@@ -1,48 +1,315 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::str::FromStr;
 
 use crate::schema::types::*;
 use itertools::Itertools;
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
 use serde_json::{json, Value};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Draft {
+  Draft04,
+  Draft06,
+  Draft07,
+  Draft201909,
+  Draft202012,
+}
+
+impl Draft {
+  pub fn schema_uri(&self) -> &'static str {
+    match self {
+      Draft::Draft04 => "http://json-schema.org/draft-04/schema#",
+      Draft::Draft06 => "http://json-schema.org/draft-06/schema#",
+      Draft::Draft07 => "http://json-schema.org/draft-07/schema#",
+      Draft::Draft201909 => "https://json-schema.org/draft/2019-09/schema",
+      Draft::Draft202012 => "https://json-schema.org/draft/2020-12/schema",
+    }
+  }
+
+  pub fn definitions_key(&self) -> &'static str {
+    if *self >= Draft::Draft201909 {
+      "$defs"
+    } else {
+      "definitions"
+    }
+  }
+}
+
+impl FromStr for Draft {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "04" | "4" => Ok(Draft::Draft04),
+      "06" | "6" => Ok(Draft::Draft06),
+      "07" | "7" => Ok(Draft::Draft07),
+      "2019-09" => Ok(Draft::Draft201909),
+      "2020-12" => Ok(Draft::Draft202012),
+      _ => Err(format!(
+        "unknown draft {}, expected one of 04, 06, 07, 2019-09, 2020-12",
+        s
+      )),
+    }
+  }
+}
+
+/// How the case of enum values is normalized
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnumCase {
+  Preserve,
+  Lower,
+  Upper,
+}
+
+impl EnumCase {
+  pub fn apply(&self, value: &str) -> String {
+    match self {
+      EnumCase::Preserve => value.to_string(),
+      EnumCase::Lower => value.to_lowercase(),
+      EnumCase::Upper => value.to_uppercase(),
+    }
+  }
+}
+
+impl FromStr for EnumCase {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "preserve" => Ok(EnumCase::Preserve),
+      "lower" => Ok(EnumCase::Lower),
+      "upper" => Ok(EnumCase::Upper),
+      _ => Err(format!(
+        "unknown enum case {}, expected one of preserve, lower, upper",
+        s
+      )),
+    }
+  }
+}
+
+pub const DEFAULT_VAR_NAME_PATTERN: &str = "^[a-zA-Z0-9_./-]+$";
+
+#[derive(Debug, Clone)]
+pub struct Options {
+  pub draft: Draft,
+  /// Pattern every key of the `vars` definition must match
+  pub var_name_pattern: String,
+  /// Definitions that live in another schema file, mapped to the pointer to the definitions map
+  /// holding them there, like `base.json#/definitions`
+  pub external_refs: HashMap<String, String>,
+  /// Whether the root accepts keys the pipeline doesn't document
+  pub root_additional_properties: bool,
+  /// Schemas of the definitions that aren't documented as schemas, such as primitives
+  pub type_map: HashMap<String, Value>,
+  /// Emit only the definitions map, to be spliced under the definitions of another schema
+  pub fragment: bool,
+  /// Case of the emitted enum and const values
+  pub enum_case: EnumCase,
+  /// Leave deprecated members out of the alternatives of their group
+  pub drop_deprecated: bool,
+  /// List the keys of closed objects in `propertyNames` too, for editors that complete from it
+  pub emit_property_names: bool,
+  /// Definitions emitted even if nothing refers to them, unless they are documented
+  pub seed_definitions: HashMap<String, Value>,
+  /// Key the definitions are kept under instead of the one of the draft
+  pub definitions_key: Option<String>,
+  /// Pretty-print with this many spaces of indentation instead of writing compact JSON
+  pub indent: Option<usize>,
+  /// Titles of definition names, or of the words in them, that title-casing gets wrong
+  pub title_overrides: HashMap<String, String>,
+  /// Type names written inline as the given schema wherever they are used, instead of as refs
+  pub primitives: HashMap<String, Value>,
+  /// Give string enum attributes without examples of their own their first value as example
+  pub auto_enum_examples: bool,
+  /// Emit string enums as a `oneOf` of titled `const`s, for editors that label completions
+  pub enum_as_oneof: bool,
+}
+
+impl Options {
+  /// The key the definitions are kept under, which refs point into
+  pub fn definitions_key(&self) -> &str {
+    self
+      .definitions_key
+      .as_deref()
+      .unwrap_or_else(|| self.draft.definitions_key())
+  }
+}
+
+/// Go durations as Concourse parses them, such as `90m` or `1h30m`
+pub const DURATION_PATTERN: &str = "^(\\d+(ns|us|µs|ms|s|m|h))+$";
+
+/// Definitions every schema has, so refs to primitives never dangle
+pub const GUARANTEED_DEFINITIONS: [&str; 5] = ["string", "number", "boolean", "integer", "value"];
+
+/// The schemas of the definitions the documentation refers to without documenting them
+pub fn builtin_type_map() -> HashMap<String, Value> {
+  HashMap::from([
+    ("string".to_string(), json!({"type":"string"})),
+    ("number".to_string(), json!({"type":"number"})),
+    ("integer".to_string(), json!({"type":"integer"})),
+    ("boolean".to_string(), json!({"type":"boolean"})),
+    ("value".to_string(), json!({})),
+    (
+      "config".to_string(),
+      json!({"type":"object","patternProperties":{".*":{"additionalProperties":true}}}),
+    ),
+    (
+      "env_vars".to_string(),
+      json!({"type":"object","patternProperties":{".*":{"additionalProperties":true}}}),
+    ),
+    (
+      "version".to_string(),
+      json!({"type":"object","patternProperties":{".*":{"type":"string"}}}),
+    ),
+    (
+      "duration".to_string(),
+      json!({"type":"string","pattern":DURATION_PATTERN}),
+    ),
+  ])
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Options {
+      draft: Draft::Draft04,
+      var_name_pattern: DEFAULT_VAR_NAME_PATTERN.to_string(),
+      external_refs: HashMap::new(),
+      root_additional_properties: true,
+      type_map: builtin_type_map(),
+      fragment: false,
+      enum_case: EnumCase::Preserve,
+      drop_deprecated: false,
+      emit_property_names: false,
+      seed_definitions: HashMap::new(),
+      definitions_key: None,
+      indent: None,
+      title_overrides: builtin_title_overrides(),
+      primitives: HashMap::new(),
+      auto_enum_examples: false,
+      enum_as_oneof: false,
+    }
+  }
+}
+
+/// Acronyms that title-case as capitals
+pub fn builtin_title_overrides() -> HashMap<String, String> {
+  [
+    "api", "aws", "ca", "cpu", "http", "https", "id", "json", "ssh", "tls", "ttl", "uri", "url",
+  ]
+  .into_iter()
+  .map(|word| (word.to_string(), word.to_uppercase()))
+  .collect()
+}
+
+/// The human-readable title of a definition: its name split into title-cased words, unless the
+/// overrides have the whole name or some of its words
+pub fn definition_title(name: &str, overrides: &HashMap<String, String>) -> String {
+  if let Some(title) = overrides.get(name) {
+    return title.clone();
+  }
+  name
+    .split(['_', '.'])
+    .filter(|word| !word.is_empty())
+    .map(|word| match overrides.get(word) {
+      Some(title) => title.clone(),
+      None => {
+        let mut chars = word.chars();
+        chars
+          .next()
+          .map(|first| first.to_uppercase().chain(chars).collect())
+          .unwrap_or_default()
+      }
+    })
+    .join(" ")
+}
+
+/// Merges the objects of `b` into those of `a`, other values of `b` replace those of `a`. Works
+/// through `b` with a stack of its own rather than recursion, copying it level by level instead
+/// of cloning it, so however deep `b` nests it can't overflow the call stack.
 fn merge(a: &mut Value, b: &Value) {
-  match (a, b) {
-    (&mut Value::Object(ref mut a), &Value::Object(ref b)) => {
-      for (k, v) in b {
-        merge(a.entry(k.clone()).or_insert(Value::Null), v);
+  let mut pending = vec![(a, b)];
+  while let Some((a, b)) = pending.pop() {
+    match (a, b) {
+      (Value::Object(a), Value::Object(b)) => {
+        for k in b.keys() {
+          a.entry(k.clone()).or_insert(Value::Null);
+        }
+        pending.extend(a.iter_mut().filter_map(|(k, a)| b.get(k).map(|b| (a, b))));
+      }
+      (a, Value::Object(_)) => {
+        *a = Value::Object(Default::default());
+        pending.push((a, b));
+      }
+      (a, Value::Array(b)) => {
+        *a = Value::Array(vec![Value::Null; b.len()]);
+        if let Value::Array(a) = a {
+          pending.extend(a.iter_mut().zip(b));
+        }
+      }
+      (a, b) => {
+        *a = b.clone();
       }
     }
-    (a, b) => {
-      *a = b.clone();
+  }
+}
+
+pub fn serialize(schema_docs: &HashMap<String, Schema>, opts: &Options) -> String {
+  let mut serialized = vec![];
+  serialize_to_writer(schema_docs, &mut serialized, opts).unwrap();
+  String::from_utf8(serialized).unwrap()
+}
+
+/// Like [`serialize`], writing the schema to `writer` as it goes instead of into a string
+pub fn serialize_to_writer<W: Write>(
+  schema_docs: &HashMap<String, Schema>,
+  writer: W,
+  opts: &Options,
+) -> serde_json::Result<()> {
+  write_json(&build_schema(schema_docs, opts), writer, opts)
+}
+
+/// Writes a schema document, compact or indented as `opts` asks
+pub fn write_json<W: Write>(schema: &Value, writer: W, opts: &Options) -> serde_json::Result<()> {
+  match opts.indent {
+    Some(indent) => {
+      let indent = " ".repeat(indent);
+      let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+      schema.serialize(&mut serde_json::Serializer::with_formatter(
+        writer, formatter,
+      ))
     }
+    None => serde_json::to_writer(writer, schema),
   }
 }
 
-pub fn serialize(schema_docs: &HashMap<String, Schema>) -> String {
+/// The schema document for the definitions, as it is serialized
+pub fn build_schema(schema_docs: &HashMap<String, Schema>, opts: &Options) -> Value {
+  if opts.emit_property_names && opts.draft < Draft::Draft06 {
+    log::warn!("propertyNames needs draft-06 or later, leaving it out");
+  }
+  if opts.enum_as_oneof && opts.draft < Draft::Draft06 {
+    log::warn!("const needs draft-06 or later, emitting enums as enum");
+  }
+
+  let extended_bases = schema_docs
+    .values()
+    .filter_map(|schema| schema.extends.as_ref())
+    .collect::<HashSet<_>>();
+
   let definitions = schema_docs
     .iter()
     .map(|(schema_name, schema)| {
       let schema_props = schema
         .properties
         .iter()
-        .map(|(prop_name, prop)| {
-          (
-            prop_name,
-            prop_type_to_jsonschema(&prop.type_name, Some(&prop.docs)),
-          )
-        })
+        .map(|(prop_name, prop)| (prop_name, property_to_jsonschema(prop, opts)))
         .collect::<HashMap<_, _>>();
 
-      let subschemas = if schema.group_members.len() > 0 {
-        prop_type_to_jsonschema(
-          &PropertyType::OneOf(
-            schema
-              .group_members
-              .iter()
-              .map(|m| PropertyType::Ref(m.to_string()))
-              .collect(),
-          ),
-          None,
-        )
+      let subschemas = if !schema.group_members.is_empty() {
+        group_schema(schema, schema_docs, opts)
       } else {
         json!({})
       };
@@ -56,12 +323,36 @@ pub fn serialize(schema_docs: &HashMap<String, Schema>) -> String {
 
       required_props.sort();
 
+      let mut schema_props = schema_props;
+      if schema.extends.is_some() {
+        // Inherited properties are validated by the base, they only need to get past
+        // additionalProperties here
+        for inherited in inherited_property_names(schema, schema_docs) {
+          schema_props.entry(inherited).or_insert_with(|| json!({}));
+        }
+      }
+
       let mut result = json!({});
 
       // let additional_properties =
       //   schema.is_group_member || (schema.group_members.len() > 0 && schema.properties.len() > 0);
 
-      if schema_props.len() > 1 {
+      if !schema_props.is_empty() && schema.layers.len() > 1 {
+        // Each block validates its own attributes, the definition only closes over all of them
+        let names = schema_props
+          .keys()
+          .map(|name| (name, json!({})))
+          .collect::<HashMap<_, _>>();
+        merge(
+          &mut result,
+          &json!({
+            "additionalProperties":false,
+            "type":"object",
+            "properties":names,
+            "allOf":schema.layers.iter().map(|props| object_schema(props.iter(), opts)).collect_vec()
+          }),
+        );
+      } else if !schema_props.is_empty() {
         merge(
           &mut result,
           &json!({
@@ -71,12 +362,39 @@ pub fn serialize(schema_docs: &HashMap<String, Schema>) -> String {
             "properties":schema_props
           }),
         );
+
+        // draft-04 requires `required` to have at least one element
+        if required_props.is_empty() && opts.draft == Draft::Draft04 {
+          result.as_object_mut().unwrap().remove("required");
+        }
+      }
+
+      if !schema_props.is_empty() {
+        // A closed base would reject the properties added by the schemas extending it. Those
+        // schemas list the inherited properties themselves, so they stay closed.
+        if extended_bases.contains(schema_name) {
+          result
+            .as_object_mut()
+            .unwrap()
+            .remove("additionalProperties");
+        } else if opts.emit_property_names && opts.draft >= Draft::Draft06 {
+          let names = schema_props.keys().sorted().collect_vec();
+          merge(&mut result, &json!({"propertyNames": {"enum": names}}));
+        }
       }
 
-      if schema.group_members.len() > 0 {
+      if !schema.group_members.is_empty() {
         merge(&mut result, &subschemas)
       }
 
+      if schema.properties.contains_key("type") && schema.properties.contains_key("source") {
+        merge(&mut result, &resource_source_schema(schema_docs, opts));
+      }
+      // Layers hold the conditions of their own attributes
+      if schema.layers.len() <= 1 {
+        add_conditions(&mut result, schema.properties.iter(), opts);
+      }
+
       log::debug!(
         "Schema {} has {} props and {} group members",
         schema_name,
@@ -84,89 +402,2516 @@ pub fn serialize(schema_docs: &HashMap<String, Schema>) -> String {
         schema.group_members.len()
       );
 
-      let schema_obj = if schema.group_members.len() > 0 || schema.properties.len() > 0 {
+      let orphan = schema
+        .properties
+        .values()
+        .next()
+        .filter(|_| schema_name.starts_with(ORPHAN_PREFIX));
+
+      let schema_obj = if let Some(prop) = orphan {
+        // An orphan stands for the single attribute it holds
+        property_to_jsonschema(prop, opts)
+      } else if !schema.group_members.is_empty() || !schema.properties.is_empty() {
         result
-      } else if schema_name == "number" {
-        json!({"type":"number"})
-      } else if schema_name == "boolean" {
-        json!({"type":"boolean"})
-      } else if schema_name == "value" {
-        json!({})
-      } else if schema_name == "config" || schema_name == "vars" || schema_name == "env_vars" {
-        json!({"type":"object","patternProperties":{".*":{"additionalProperties":true}}})
-      } else if schema_name == "version" {
-        json!({"type":"object","patternProperties":{".*":{"type":"string"}}})
+      } else if let Some(mapped) = opts.type_map.get(schema_name) {
+        mapped.clone()
+      } else if schema_name == "vars" {
+        vars_schema(opts)
+      } else if schema.docs.is_empty() && !PRIMITIVE_TYPES.contains(&schema_name.as_str()) {
+        // Without attributes or prose there is nothing suggesting a string, an empty body is most
+        // likely an object type whose attributes aren't documented
+        json!({"type":"object"})
       } else {
         json!({"type": "string"})
       };
 
+      let schema_obj = match &schema.extends {
+        Some(base) => json!({
+          "allOf": [
+            {"$ref": ref_pointer(base, opts)},
+            schema_obj
+          ]
+        }),
+        None => schema_obj,
+      };
+
+      let mut schema_obj = if schema.docs.is_empty() {
+        schema_obj
+      } else {
+        let mut documented = schema_obj;
+        merge(&mut documented, &json!({ "description": schema.docs }));
+        documented
+      };
+
+      merge(
+        &mut schema_obj,
+        &extensions_to_jsonschema(&schema.extensions),
+      );
+
+      if !schema.examples.is_empty() {
+        // Examples written as JSON become values, others stay the snippet they are
+        let examples = schema
+          .examples
+          .iter()
+          .map(|example| serde_json::from_str(example).unwrap_or_else(|_| json!(example)))
+          .collect_vec();
+        merge(&mut schema_obj, &json!({ "examples": examples }));
+      }
+
+      if schema.deprecated {
+        merge(&mut schema_obj, &json!({ "deprecated": true }));
+      }
+
       (schema_name, schema_obj)
     })
     .collect::<HashMap<_, _>>();
 
+  // Pseudo-types like `duration` are only known to the type map, so they get a definition as soon
+  // as an attribute refers to them
+  let mut definitions = definitions;
+  let referenced = referenced_names(schema_docs);
+  for (name, mapped) in &opts.type_map {
+    let guaranteed = GUARANTEED_DEFINITIONS.contains(&name.as_str());
+    if !schema_docs.contains_key(name) && (guaranteed || referenced.contains(name)) {
+      definitions.insert(name, mapped.clone());
+    }
+  }
+  for (name, seed) in &opts.seed_definitions {
+    if !schema_docs.contains_key(name) {
+      definitions.insert(name, seed.clone());
+    }
+  }
+
+  // Definitions referred to in another file are only emitted there
+  definitions.retain(|name, _| !opts.external_refs.contains_key(*name));
+
+  for (name, definition) in definitions.iter_mut() {
+    if let Some(definition) = definition.as_object_mut() {
+      if !name.starts_with(ORPHAN_PREFIX) && !definition.contains_key("title") {
+        let title = definition_title(name, &opts.title_overrides);
+        definition.insert("title".to_string(), json!(title));
+      }
+      // Type map and seed definitions are referred to by anchor as well
+      if uses_anchor(name, opts) {
+        definition.insert("$anchor".to_string(), json!(name));
+      }
+    }
+  }
+
+  let mut definitions = json!(definitions);
+  normalize_numbers(&mut definitions);
+
+  if opts.fragment {
+    return definitions;
+  }
+
+  if opts.root_additional_properties {
+    return json!({
+      "$schema": opts.draft.schema_uri(),
+      "$ref": ref_pointer("pipeline", opts),
+      "additionalProperties": true,
+      opts.definitions_key(): definitions
+    });
+  }
+
+  // Keywords next to a `$ref` are ignored before 2019-09, and additionalProperties only knows the
+  // properties listed next to it, so a closed root lists the pipeline's properties itself
+  let root_props = root_property_names(schema_docs)
+    .into_iter()
+    .map(|name| (name, json!({})))
+    .collect::<HashMap<_, _>>();
+
   json!({
-    "$schema": "http://json-schema.org/draft-04/schema#",
-    "$ref": "#/definitions/pipeline",
-    "additionalProperties": true,
-    "definitions": definitions
+    "$schema": opts.draft.schema_uri(),
+    "allOf": [{"$ref": ref_pointer("pipeline", opts)}],
+    "properties": root_props,
+    "additionalProperties": false,
+    opts.definitions_key(): definitions
   })
-  .to_string()
 }
 
-fn prop_type_to_jsonschema_nodocs(prop_type: &PropertyType) -> Value {
-  prop_type_to_jsonschema(prop_type, None)
+/// The attributes one of the blocks of a reopened schema adds, without closing the object
+fn object_schema<'a>(
+  props: impl Iterator<Item = (&'a String, &'a Property)> + Clone,
+  opts: &Options,
+) -> Value {
+  let required = props
+    .clone()
+    .filter(|(_, prop)| prop.required)
+    .map(|(name, _)| name)
+    .sorted()
+    .collect_vec();
+  let properties = props
+    .clone()
+    .map(|(name, prop)| (name, property_to_jsonschema(prop, opts)))
+    .collect::<HashMap<_, _>>();
+
+  let mut layer = json!({"type":"object","properties":properties});
+  if !required.is_empty() || opts.draft > Draft::Draft04 {
+    layer["required"] = json!(required);
+  }
+  add_conditions(&mut layer, props, opts);
+  layer
 }
 
-fn prop_type_to_jsonschema(prop_type: &PropertyType, description: Option<&String>) -> Value {
-  let mut prop_schema = match prop_type {
-    PropertyType::OneOf(types) => {
-      let all_types_count = types.len();
+/// Requires the attributes documented with `\required-if` when their condition holds. Before
+/// draft-07 each `if`/`then` pair is written as the equivalent `not if` or `then`.
+fn add_conditions<'a>(
+  object: &mut Value,
+  props: impl Iterator<Item = (&'a String, &'a Property)>,
+  opts: &Options,
+) {
+  let props = props.collect_vec();
+  let sibling = |attribute: &String| {
+    props
+      .iter()
+      .find(|(name, _)| *name == attribute)
+      .map(|(_, prop)| *prop)
+  };
+  let conditions = props
+    .iter()
+    .flat_map(|(name, prop)| {
+      prop
+        .required_if
+        .iter()
+        .map(move |condition| (name, condition))
+    })
+    .sorted()
+    .map(|(name, (attribute, value))| {
+      let condition = json!({
+        "properties": {attribute: {"enum": [condition_value(value, sibling(attribute), opts)]}},
+        "required": [attribute]
+      });
+      let then = json!({ "required": [name] });
+      if opts.draft >= Draft::Draft07 {
+        json!({"if": condition, "then": then})
+      } else {
+        json!({"anyOf": [{"not": condition}, then]})
+      }
+    })
+    .collect_vec();
 
-      assert!(all_types_count > 0);
+  if conditions.is_empty() {
+    return;
+  }
+  match object.get_mut("allOf").and_then(Value::as_array_mut) {
+    Some(all_of) => all_of.extend(conditions),
+    None => object["allOf"] = json!(conditions),
+  }
+}
 
-      let all_strings = types
+/// The value a `\required-if` compares its sibling attribute to. JSON literals like `true` or `1`
+/// keep their type, unless the sibling is a string or has the value as one of its constants,
+/// which are emitted the way constants are.
+fn condition_value(value: &str, sibling: Option<&Property>, opts: &Options) -> Value {
+  fn as_constant(prop_type: &PropertyType, value: &str) -> bool {
+    match prop_type {
+      PropertyType::Ref(name) => name == "string",
+      PropertyType::Constant(constant, _) => constant == value,
+      PropertyType::OneOf(types) if types.len() == 1 => as_constant(&types[0], value),
+      PropertyType::OneOf(types) => types
         .iter()
-        .map(|p| match p {
-          PropertyType::Constant(c) => c.to_string(),
-          _ => "".to_string(),
-        })
-        .filter(|p| p != "")
-        .collect::<Vec<_>>();
+        .any(|t| matches!(t, PropertyType::Constant(constant, _) if constant == value)),
+      _ => false,
+    }
+  }
 
-      if all_strings.len() == all_types_count {
-        log::debug!("Enum type {:?}", prop_type);
-        json!({"type":"string","enum":all_strings})
-      } else if all_types_count == 1 {
-        prop_type_to_jsonschema_nodocs(&types[0])
-      } else {
-        json!({"oneOf": types.iter().map(prop_type_to_jsonschema_nodocs).collect_vec()})
+  let as_constant = sibling.is_some_and(|sibling| as_constant(&sibling.type_name, value));
+  match serde_json::from_str::<Value>(value) {
+    Ok(literal @ (Value::Bool(_) | Value::Number(_) | Value::Null)) if !as_constant => literal,
+    _ => constant_to_json(value, opts),
+  }
+}
+
+/// Drops definitions whose content is identical to another's, pointing their refs to the one that
+/// is kept: `pipeline`, or else the first name alphabetically. Repeats until nothing changes, as
+/// rewritten refs can make more definitions identical.
+///
+/// `schema` is a document built with `opts`: its definitions are the document itself for a
+/// fragment.
+pub fn dedup_definitions(schema: &mut Value, opts: &Options) {
+  let definitions = if opts.fragment {
+    schema.as_object_mut()
+  } else {
+    schema
+      .get_mut(opts.definitions_key())
+      .and_then(Value::as_object_mut)
+  };
+  let definitions = match definitions {
+    Some(definitions) => definitions,
+    None => return,
+  };
+
+  // Anchors and titles follow from the name, not the content
+  let content = |definition: &Value| {
+    let mut content = definition.clone();
+    if let Some(object) = content.as_object_mut() {
+      object.remove("$anchor");
+      object.remove("title");
+    }
+    content.to_string()
+  };
+
+  loop {
+    let mut by_hash: HashMap<u64, Vec<String>> = HashMap::new();
+    for (name, definition) in definitions.iter() {
+      let mut hasher = std::collections::hash_map::DefaultHasher::new();
+      content(definition).hash(&mut hasher);
+      by_hash
+        .entry(hasher.finish())
+        .or_default()
+        .push(name.to_string());
+    }
+
+    let mut duplicates = vec![];
+    let mut replacements = HashMap::new();
+    for mut names in by_hash.into_values() {
+      names.sort_by_key(|name| (name != "pipeline", name.clone()));
+      while let Some((canonical, others)) = names.split_first() {
+        let canonical_content = content(&definitions[canonical]);
+        // Names sharing a hash still get compared, in case of a collision
+        let (same, different): (Vec<String>, Vec<String>) = others
+          .iter()
+          .cloned()
+          .partition(|name| content(&definitions[name]) == canonical_content);
+        for name in same {
+          replacements.insert(ref_pointer(&name, opts), ref_pointer(canonical, opts));
+          duplicates.push(name);
+        }
+        names = different;
       }
     }
-    PropertyType::ArrayOf(inner) => json!({
-      "type":"array",
-      "items":prop_type_to_jsonschema(inner, description)
-    }),
-    PropertyType::Constant(item) => json!({
-      "type":"string",
-      "enum":[item]
-    }),
-    PropertyType::Dict => {
-      json!({
-        "type":"object",
-        "patternProperties":{".*":{"type":"string"}}
-      })
+
+    if duplicates.is_empty() {
+      return;
     }
-    PropertyType::Ref(item) => {
-      json!({
-        "$ref":"#/definitions/".to_string() + item.replace("\\", "\\\\").as_str()
-      })
+
+    log::debug!("Deduplicating {}", duplicates.join(", "));
+    definitions.retain(|name, _| !duplicates.contains(name));
+    for definition in definitions.values_mut() {
+      rewrite_refs(definition, &replacements);
+    }
+  }
+}
+
+/// Cuts descriptions longer than `max` characters at a word boundary and adds an ellipsis,
+/// keeping the full text in `x-full-description`
+pub fn truncate_descriptions(schema: &mut Value, max: usize) {
+  let entries = match schema {
+    Value::Object(entries) => entries,
+    Value::Array(items) => {
+      items
+        .iter_mut()
+        .for_each(|item| truncate_descriptions(item, max));
+      return;
     }
+    _ => return,
   };
 
-  match description {
-    Some(docs) => merge(&mut prop_schema, &json!({ "description": docs })),
-    None => (),
+  if let Some(Value::String(description)) = entries.get("description") {
+    if description.chars().count() > max {
+      let full = description.clone();
+      let cut = full.char_indices().nth(max).map_or(full.len(), |(i, _)| i);
+      let head = &full[..cut];
+      // The last whole word, unless the first word alone is longer than max
+      let head = match head.rfind(char::is_whitespace) {
+        Some(end) if !full[cut..].starts_with(char::is_whitespace) => &head[..end],
+        _ => head,
+      };
+      entries.insert(
+        "description".to_string(),
+        json!(format!("{}…", head.trim_end())),
+      );
+      entries.insert("x-full-description".to_string(), json!(full));
+    }
+  }
+
+  for (key, value) in entries.iter_mut() {
+    // Values, unlike schemas, can have a description of their own
+    if !["examples", "default", "const", "enum"].contains(&key.as_str()) {
+      truncate_descriptions(value, max);
+    }
+  }
+}
+
+/// Closes the code spans and emphasis that descriptions leave open at the end of a paragraph, so
+/// malformed docs still render as intended
+pub fn sanitize_descriptions(schema: &mut Value) {
+  rewrite_descriptions(schema, &balance_markdown);
+}
+
+/// Collapses the runs of spaces in descriptions and trims their lines, leaving code as it is
+pub fn minify_descriptions(schema: &mut Value) {
+  rewrite_descriptions(schema, &minify_markdown);
+}
+
+fn rewrite_descriptions(schema: &mut Value, rewrite: &dyn Fn(&str) -> String) {
+  let entries = match schema {
+    Value::Object(entries) => entries,
+    Value::Array(items) => {
+      items
+        .iter_mut()
+        .for_each(|item| rewrite_descriptions(item, rewrite));
+      return;
+    }
+    _ => return,
   };
 
-  prop_schema
+  for key in ["description", "x-full-description"] {
+    if let Some(Value::String(description)) = entries.get_mut(key) {
+      *description = rewrite(description);
+    }
+  }
+
+  for (key, value) in entries.iter_mut() {
+    if !["examples", "default", "const", "enum"].contains(&key.as_str()) {
+      rewrite_descriptions(value, rewrite);
+    }
+  }
+}
+
+fn minify_markdown(text: &str) -> String {
+  let mut in_fence = false;
+  text
+    .lines()
+    .map(|line| {
+      let is_fence = line.trim_start().starts_with("```");
+      if in_fence || is_fence || line.starts_with("    ") {
+        in_fence ^= is_fence;
+        return line.to_string();
+      }
+      line.split(' ').filter(|word| !word.is_empty()).join(" ")
+    })
+    .join("\n")
+}
+
+/// Balances the inline markers of each paragraph of `text`. Fenced and indented code is left as
+/// it is.
+fn balance_markdown(text: &str) -> String {
+  let mut lines = text.lines().map(String::from).collect_vec();
+  let mut in_fence = false;
+  let mut paragraph: Vec<usize> = vec![];
+  for i in 0..=lines.len() {
+    let line = lines.get(i).map_or("", String::as_str);
+    let is_fence = line.trim_start().starts_with("```");
+    if in_fence || is_fence || line.trim().is_empty() || line.starts_with("    ") {
+      if let Some(&last) = paragraph.last() {
+        let closers = unclosed_markers(&paragraph.iter().map(|&j| &lines[j]).join("\n"));
+        if !closers.is_empty() {
+          lines[last] = format!("{}{}", lines[last].trim_end(), closers);
+        }
+        paragraph.clear();
+      }
+      in_fence ^= is_fence;
+    } else {
+      paragraph.push(i);
+    }
+  }
+
+  let balanced = lines.join("\n");
+  if text.ends_with('\n') {
+    balanced + "\n"
+  } else {
+    balanced
+  }
+}
+
+/// The markers that close what a paragraph leaves open, innermost first
+fn unclosed_markers(paragraph: &str) -> String {
+  let chars = paragraph.chars().collect_vec();
+  let run = |i: usize| chars[i..].iter().take_while(|&&c| c == chars[i]).count();
+  let mut open: Vec<String> = vec![];
+  let mut i = 0;
+  while i < chars.len() {
+    match chars[i] {
+      '\\' => i += 2,
+      '`' => {
+        let n = run(i);
+        // A code span ends at the next run of as many backticks
+        let mut end = i + n;
+        while end < chars.len() && !(chars[end] == '`' && run(end) == n) {
+          end += if chars[end] == '`' { run(end) } else { 1 };
+        }
+        if end >= chars.len() {
+          open.push("`".repeat(n));
+          break;
+        }
+        i = end + n;
+      }
+      c @ ('*' | '~') => {
+        let n = run(i);
+        let before = i.checked_sub(1).map(|j| chars[j]);
+        let after = chars.get(i + n).copied();
+        // Runs between spaces are literal, as are list bullets at the start of a line
+        let flanking =
+          !(before.is_none_or(char::is_whitespace) && after.is_none_or(char::is_whitespace));
+        if flanking && (c == '*' || n == 2) {
+          let markers = if c == '~' {
+            vec!["~~"]
+          } else {
+            std::iter::repeat_n("**", n / 2)
+              .chain((n % 2 == 1).then_some("*"))
+              .collect_vec()
+          };
+          for marker in markers {
+            match open.iter().rposition(|m| m == marker) {
+              Some(at) => {
+                open.remove(at);
+              }
+              None => open.push(marker.to_string()),
+            }
+          }
+        }
+        i += n;
+      }
+      _ => i += 1,
+    }
+  }
+  open.into_iter().rev().collect()
+}
+
+/// Keywords that only document a schema, which can be merged into the definition a ref is inlined
+/// as without changing what validates
+const ANNOTATIONS: [&str; 6] = [
+  "description",
+  "$comment",
+  "title",
+  "default",
+  "examples",
+  "deprecated",
+];
+
+/// Replaces every ref to a definition of `schema`, a document built with `opts`, by the content of
+/// that definition. Refs back into a definition being inlined stay refs, so only the definitions
+/// these cycles point to are kept.
+pub fn bundle(schema: &mut Value, opts: &Options) {
+  let key = opts.definitions_key();
+  let definitions = if opts.fragment {
+    std::mem::take(schema)
+  } else {
+    schema
+      .as_object_mut()
+      .and_then(|root| root.remove(key))
+      .unwrap_or_default()
+  };
+  let definitions = match definitions {
+    Value::Object(definitions) => definitions,
+    _ => return,
+  };
+  let names = definitions
+    .keys()
+    .map(|name| (ref_pointer(name, opts), name.clone()))
+    .collect::<HashMap<_, _>>();
+
+  let mut kept = serde_json::Map::new();
+  let mut pending = if opts.fragment {
+    definitions.keys().cloned().collect_vec()
+  } else {
+    inline_refs(schema, &definitions, &names, &mut vec![]);
+    remaining_refs(schema, &names)
+  };
+  while let Some(name) = pending.pop() {
+    if kept.contains_key(&name) {
+      continue;
+    }
+    let mut definition = definitions[&name].clone();
+    inline_refs(
+      &mut definition,
+      &definitions,
+      &names,
+      &mut vec![name.clone()],
+    );
+    pending.extend(remaining_refs(&definition, &names));
+    kept.insert(name, definition);
+  }
+
+  if opts.fragment {
+    *schema = Value::Object(kept);
+  } else if !kept.is_empty() {
+    schema[key] = Value::Object(kept);
+  }
+}
+
+fn inline_refs(
+  value: &mut Value,
+  definitions: &serde_json::Map<String, Value>,
+  names: &HashMap<String, String>,
+  inlining: &mut Vec<String>,
+) {
+  let name = value
+    .get("$ref")
+    .and_then(Value::as_str)
+    .and_then(|pointer| names.get(pointer))
+    .filter(|name| !inlining.contains(name))
+    .cloned();
+
+  if let Some(name) = name {
+    let mut inlined = definitions[&name].clone();
+    if let Some(object) = inlined.as_object_mut() {
+      object.remove("$anchor");
+    }
+    inlining.push(name);
+    inline_refs(&mut inlined, definitions, names, inlining);
+    inlining.pop();
+
+    let mut siblings = value.as_object().cloned().unwrap_or_default();
+    siblings.remove("$ref");
+    *value = if siblings
+      .keys()
+      .all(|key| ANNOTATIONS.contains(&key.as_str()))
+    {
+      merge(&mut inlined, &Value::Object(siblings));
+      inlined
+    } else {
+      siblings.insert("allOf".to_string(), json!([inlined]));
+      Value::Object(siblings)
+    };
+    return;
+  }
+
+  match value {
+    Value::Object(entries) => {
+      for (key, entry) in entries.iter_mut() {
+        // Values can look like schemas without being ones
+        if !["examples", "default", "const", "enum"].contains(&key.as_str()) {
+          inline_refs(entry, definitions, names, inlining);
+        }
+      }
+    }
+    Value::Array(items) => items
+      .iter_mut()
+      .for_each(|item| inline_refs(item, definitions, names, inlining)),
+    _ => {}
+  }
+}
+
+/// The definitions refs in `value` still point to
+fn remaining_refs(value: &Value, names: &HashMap<String, String>) -> Vec<String> {
+  match value {
+    Value::Object(entries) => entries
+      .iter()
+      .flat_map(|(key, entry)| match entry {
+        Value::String(pointer) if key == "$ref" => {
+          names.get(pointer).cloned().into_iter().collect()
+        }
+        entry => remaining_refs(entry, names),
+      })
+      .collect(),
+    Value::Array(items) => items
+      .iter()
+      .flat_map(|item| remaining_refs(item, names))
+      .collect(),
+    _ => vec![],
+  }
+}
+
+fn rewrite_refs(value: &mut Value, replacements: &HashMap<String, String>) {
+  match value {
+    Value::Object(entries) => {
+      for (key, entry) in entries.iter_mut() {
+        match entry {
+          Value::String(pointer) if key == "$ref" => {
+            if let Some(canonical) = replacements.get(pointer.as_str()) {
+              *pointer = canonical.clone();
+            }
+          }
+          other => rewrite_refs(other, replacements),
+        }
+      }
+    }
+    Value::Array(items) => items
+      .iter_mut()
+      .for_each(|item| rewrite_refs(item, replacements)),
+    _ => {}
+  }
+}
+
+/// Writes integral floats as integers, so `1.0` and `1` come out the same however they were
+/// written or computed
+fn normalize_numbers(value: &mut Value) {
+  match value {
+    Value::Number(number) if !number.is_i64() && !number.is_u64() => {
+      if let Some(float) = number.as_f64() {
+        if float.fract() == 0.0 && float.abs() < i64::MAX as f64 {
+          *value = json!(float as i64);
+        }
+      }
+    }
+    Value::Array(items) => items.iter_mut().for_each(normalize_numbers),
+    Value::Object(entries) => entries.values_mut().for_each(normalize_numbers),
+    _ => {}
+  }
+}
+
+/// Attribute types referring to a definition that is neither documented, in the type map, a
+/// primitive nor external
+pub fn dangling_refs(schema_docs: &HashMap<String, Schema>, opts: &Options) -> Vec<String> {
+  let known = |name: &String| {
+    schema_docs.contains_key(name)
+      || opts.type_map.contains_key(name)
+      || opts.primitives.contains_key(name)
+      || opts.seed_definitions.contains_key(name)
+      || opts.external_refs.contains_key(name)
+  };
+
+  schema_docs
+    .values()
+    .sorted_by_key(|schema| &schema.schema_name)
+    .flat_map(|schema| {
+      schema
+        .properties
+        .iter()
+        .sorted_by_key(|(prop_name, _)| *prop_name)
+        .flat_map(move |(prop_name, prop)| {
+          let mut names = HashSet::new();
+          property_refs(prop, &mut names);
+          names
+            .into_iter()
+            .filter(|name| !known(name))
+            .sorted()
+            .map(move |name| {
+              format!(
+                "In schema {}, attribute {}: refers to undefined definition {}",
+                schema.schema_name, prop_name, name
+              )
+            })
+        })
+    })
+    .collect()
+}
+
+/// The examples of an attribute that read as JSON values. Others are usually snippets of the
+/// surrounding YAML, which only the description shows.
+fn json_examples(prop: &Property) -> impl Iterator<Item = (&String, Value)> {
+  prop
+    .examples
+    .iter()
+    .filter_map(|example| Some((example, serde_json::from_str(example).ok()?)))
+}
+
+/// Attribute examples that don't have the type of their attribute. Refs to documented schemas
+/// only check that the example is an object, if the schema has attributes.
+pub fn example_problems(schema_docs: &HashMap<String, Schema>, opts: &Options) -> Vec<String> {
+  schema_docs
+    .values()
+    .sorted_by_key(|schema| &schema.schema_name)
+    .flat_map(|schema| {
+      schema
+        .properties
+        .iter()
+        .sorted_by_key(|(prop_name, _)| *prop_name)
+        .flat_map(move |(prop_name, prop)| {
+          json_examples(prop)
+            .filter(|(_, example)| !example_matches(&prop.type_name, example, schema_docs, opts))
+            .map(move |(text, _)| {
+              format!(
+                "In schema {}, attribute {}: example {} doesn't match its type",
+                schema.schema_name, prop_name, text
+              )
+            })
+        })
+    })
+    .collect()
+}
+
+fn example_matches(
+  prop_type: &PropertyType,
+  example: &Value,
+  schema_docs: &HashMap<String, Schema>,
+  opts: &Options,
+) -> bool {
+  let matches = |prop_type| example_matches(prop_type, example, schema_docs, opts);
+  match prop_type {
+    PropertyType::OneOf(types) => types.iter().any(matches),
+    PropertyType::Not(inner) => !matches(inner),
+    PropertyType::Constant(value, _) => constant_to_json(value, opts) == *example,
+    PropertyType::ArrayOf(inner) => example.as_array().is_some_and(|items| {
+      items
+        .iter()
+        .all(|item| example_matches(inner, item, schema_docs, opts))
+    }),
+    PropertyType::Dict { value, .. } => example.as_object().is_some_and(|entries| {
+      entries
+        .values()
+        .all(|entry| example_matches(value, entry, schema_docs, opts))
+    }),
+    PropertyType::Ref(name) => match schema_docs.get(name) {
+      Some(schema) if !schema.properties.is_empty() => example.is_object(),
+      Some(_) => true,
+      None => match opts
+        .type_map
+        .get(name)
+        .and_then(|mapped| mapped.get("type"))
+      {
+        Some(Value::String(json_type)) => has_json_type(example, json_type),
+        Some(Value::Array(json_types)) => json_types
+          .iter()
+          .filter_map(Value::as_str)
+          .any(|json_type| has_json_type(example, json_type)),
+        _ => true,
+      },
+    },
+  }
+}
+
+fn has_json_type(value: &Value, json_type: &str) -> bool {
+  match json_type {
+    "string" => value.is_string(),
+    "number" => value.is_number(),
+    "integer" => value.is_i64() || value.is_u64(),
+    "boolean" => value.is_boolean(),
+    "array" => value.is_array(),
+    "object" => value.is_object(),
+    "null" => value.is_null(),
+    _ => true,
+  }
+}
+
+/// What would keep definitions that weren't converted from litfiles, like a hand-edited model read
+/// with `--from-model`, from being serialized: extensions that aren't JSON and unions without any
+/// alternatives
+pub fn model_problems(schema_docs: &HashMap<String, Schema>) -> Vec<String> {
+  fn extension_problems(extensions: &BTreeMap<String, String>) -> Vec<String> {
+    extensions
+      .iter()
+      .filter_map(|(key, value)| {
+        let e = serde_json::from_str::<Value>(value).err()?;
+        Some(format!("extension {} isn't JSON: {}", key, e))
+      })
+      .collect()
+  }
+  fn has_empty_union(prop_type: &PropertyType) -> bool {
+    match prop_type {
+      PropertyType::OneOf(types) => types.is_empty() || types.iter().any(has_empty_union),
+      PropertyType::ArrayOf(inner) | PropertyType::Not(inner) => has_empty_union(inner),
+      PropertyType::Dict { key, value } => has_empty_union(key) || has_empty_union(value),
+      PropertyType::Constant(..) | PropertyType::Ref(_) => false,
+    }
+  }
+  fn property_problems(prop: &Property) -> Vec<String> {
+    let mut problems = extension_problems(&prop.extensions);
+    if has_empty_union(&prop.type_name) {
+      problems.push("type has a union without alternatives".to_string());
+    }
+    problems
+  }
+  fn attribute_problems(name: String, prop: &Property, problems: &mut Vec<(String, String)>) {
+    for problem in property_problems(prop) {
+      problems.push((name.clone(), problem));
+    }
+    for (attribute, nested) in &prop.attributes {
+      attribute_problems(format!("{}.{}", name, attribute), nested, problems);
+    }
+  }
+
+  schema_docs
+    .values()
+    .sorted_by_key(|schema| &schema.schema_name)
+    .flat_map(|schema| {
+      let mut attributes = vec![];
+      for (name, prop) in schema
+        .properties
+        .iter()
+        .chain(schema.layers.iter().flatten())
+      {
+        attribute_problems(name.clone(), prop, &mut attributes);
+      }
+      let attributes = attributes
+        .into_iter()
+        .sorted()
+        .dedup()
+        .map(|(name, problem)| {
+          format!(
+            "In schema {}, attribute {}: {}",
+            schema.schema_name, name, problem
+          )
+        });
+      extension_problems(&schema.extensions)
+        .into_iter()
+        .map(|problem| format!("In schema {}: {}", schema.schema_name, problem))
+        .chain(attributes)
+        .collect_vec()
+    })
+    .collect()
+}
+
+/// Reasons the schema serialized from these definitions would be broken: no definitions at all,
+/// or no `pipeline` definition for the root to refer to
+pub fn root_problems(schema_docs: &HashMap<String, Schema>) -> Vec<String> {
+  if schema_docs.is_empty() {
+    vec!["No definitions were found, the schema would be empty".to_string()]
+  } else if !schema_docs.contains_key("pipeline") {
+    vec!["No pipeline definition was found, the root of the schema refers to nothing".to_string()]
+  } else {
+    vec![]
+  }
+}
+
+/// Every property the pipeline can have: its own, inherited ones and those of its group members
+fn root_property_names(schema_docs: &HashMap<String, Schema>) -> Vec<&String> {
+  let mut names = vec![];
+  let mut pending = vec!["pipeline".to_string()];
+  let mut visited = HashSet::new();
+
+  while let Some(name) = pending.pop() {
+    if !visited.insert(name.clone()) {
+      continue;
+    }
+    if let Some(schema) = schema_docs.get(&name) {
+      names.extend(schema.properties.keys());
+      names.extend(inherited_property_names(schema, schema_docs));
+      pending.extend(schema.group_members.iter().cloned());
+    }
+  }
+
+  names.into_iter().unique().collect()
+}
+
+/// A group validates as one of its members. With a discriminator key whose value identifies each
+/// member, draft-07+ gets an if/then per member instead, so errors point at the matching member.
+fn group_schema(schema: &Schema, schema_docs: &HashMap<String, Schema>, opts: &Options) -> Value {
+  let members = group_alternatives(schema, schema_docs, opts);
+  let mut one_of = prop_type_to_jsonschema(
+    &PropertyType::OneOf(
+      members
+        .iter()
+        .map(|m| PropertyType::Ref(m.to_string()))
+        .collect(),
+    ),
+    None,
+    opts,
+  );
+
+  let key = match &schema.discriminator {
+    Some(key) => key,
+    None => return one_of,
+  };
+
+  let branches = members
+    .into_iter()
+    .map(|member| {
+      schema_docs
+        .get(member)
+        .and_then(|member_schema| member_schema.properties.get(key))
+        .and_then(|prop| constant_values(&prop.type_name))
+        .map(|values| (member, constants_to_json(values, opts)))
+    })
+    .collect::<Option<Vec<_>>>();
+
+  match branches {
+    Some(branches) if opts.draft >= Draft::Draft07 => {
+      let all_values = branches.iter().flat_map(|(_, values)| values).collect_vec();
+      json!({
+        "type": "object",
+        "required": [key],
+        "properties": {key: {"enum": all_values}},
+        "allOf": branches.iter().map(|(member, values)| json!({
+          "if": {"properties": {key: {"enum": values}}, "required": [key]},
+          "then": {"$ref": ref_pointer(member, opts)}
+        })).collect_vec()
+      })
+    }
+    _ => {
+      merge(
+        &mut one_of,
+        &json!({"discriminator": {"propertyName": key}}),
+      );
+      one_of
+    }
+  }
+}
+
+/// The members a group validates as. With `drop_deprecated` these are only the members that
+/// aren't deprecated, unless all of them are.
+fn group_alternatives<'a>(
+  schema: &'a Schema,
+  schema_docs: &HashMap<String, Schema>,
+  opts: &Options,
+) -> Vec<&'a String> {
+  let is_deprecated = |member: &String| {
+    schema_docs
+      .get(member)
+      .is_some_and(|member_schema| member_schema.deprecated)
+  };
+
+  let current = schema
+    .group_members
+    .iter()
+    .filter(|member| !is_deprecated(member))
+    .collect_vec();
+
+  if !opts.drop_deprecated || current.len() == schema.group_members.len() {
+    return schema.group_members.iter().collect();
+  }
+  if current.is_empty() {
+    log::warn!(
+      "All members of {} are deprecated, keeping them",
+      schema.schema_name
+    );
+    return schema.group_members.iter().collect();
+  }
+  current
+}
+
+/// Validates the `source` of a resource against the schema documented for its `type`. Before
+/// draft-07 each `if`/`then` pair is written as the equivalent `not if` or `then`.
+fn resource_source_schema(schema_docs: &HashMap<String, Schema>, opts: &Options) -> Value {
+  let resource_types = schema_docs
+    .values()
+    .filter_map(|schema| {
+      schema
+        .resource_type
+        .as_ref()
+        .map(|resource_type| (resource_type, &schema.schema_name))
+    })
+    .sorted()
+    .collect_vec();
+
+  if resource_types.is_empty() {
+    return json!({});
+  }
+
+  let conditions = resource_types
+    .iter()
+    .map(|(resource_type, schema_name)| {
+      let condition =
+        json!({"properties": {"type": {"enum": [resource_type]}}, "required": ["type"]});
+      let source = json!({"properties": {"source": {"$ref": ref_pointer(schema_name, opts)}}});
+      if opts.draft >= Draft::Draft07 {
+        json!({"if": condition, "then": source})
+      } else {
+        json!({"anyOf": [{"not": condition}, source]})
+      }
+    })
+    .collect_vec();
+
+  json!({ "allOf": conditions })
+}
+
+/// The JSON value of a constant. Constants written as a JSON number, like `8080`, are numbers,
+/// others are strings in the case `opts` asks for.
+pub fn constant_to_json(value: &str, opts: &Options) -> Value {
+  match serde_json::from_str(value) {
+    // Normalized right away, so `1.0` is typed as an integer like `1` is
+    Ok(mut number @ Value::Number(_)) => {
+      normalize_numbers(&mut number);
+      number
+    }
+    _ => json!(opts.enum_case.apply(value)),
+  }
+}
+
+/// The distinct JSON values of constants, in order
+fn constants_to_json<'a>(
+  values: impl IntoIterator<Item = &'a String>,
+  opts: &Options,
+) -> Vec<Value> {
+  values
+    .into_iter()
+    .map(|value| constant_to_json(value, opts))
+    .unique_by(Value::to_string)
+    .collect()
+}
+
+/// The `type` of an enum of these values, a list of types if they are mixed
+fn constants_type(values: &[Value]) -> Value {
+  let mut types = values
+    .iter()
+    .map(|value| match value {
+      Value::Number(number) if number.is_f64() => "number",
+      Value::Number(_) => "integer",
+      _ => "string",
+    })
+    .unique()
+    .sorted()
+    .collect_vec();
+  if types.contains(&"number") {
+    types.retain(|t| *t != "integer");
+  }
+
+  if types.len() == 1 {
+    json!(types[0])
+  } else {
+    json!(types)
+  }
+}
+
+fn constant_values(prop_type: &PropertyType) -> Option<Vec<&String>> {
+  match prop_type {
+    PropertyType::Constant(value, _) => Some(vec![value]),
+    PropertyType::OneOf(types) => types
+      .iter()
+      .map(|t| match t {
+        PropertyType::Constant(value, _) => Some(value),
+        _ => None,
+      })
+      .collect(),
+    _ => None,
+  }
+}
+
+fn inherited_property_names<'a>(
+  schema: &Schema,
+  schema_docs: &'a HashMap<String, Schema>,
+) -> Vec<&'a String> {
+  let mut names = vec![];
+  let mut visited = vec![&schema.schema_name];
+  let mut base = schema.extends.as_ref().and_then(|b| schema_docs.get(b));
+
+  while let Some(base_schema) = base {
+    if visited.contains(&&base_schema.schema_name) {
+      break;
+    }
+    visited.push(&base_schema.schema_name);
+    names.extend(base_schema.properties.keys());
+    base = base_schema
+      .extends
+      .as_ref()
+      .and_then(|b| schema_docs.get(b));
+  }
+
+  names
+}
+
+fn vars_schema(opts: &Options) -> Value {
+  let value_schema = json!({"additionalProperties":true});
+
+  // propertyNames only exists since draft-06, so under draft-04 the same constraint is expressed by
+  // only allowing keys that match the pattern
+  if opts.draft >= Draft::Draft06 {
+    json!({
+      "type":"object",
+      "propertyNames":{"pattern":opts.var_name_pattern},
+      "patternProperties":{".*":value_schema}
+    })
+  } else {
+    json!({
+      "type":"object",
+      "additionalProperties":false,
+      "patternProperties":{opts.var_name_pattern.as_str():value_schema}
+    })
+  }
+}
+
+/// Under 2020-12 definitions are referenced by their `$anchor`, provided the name is a valid
+/// anchor (`[A-Za-z_][-A-Za-z0-9._]*`)
+fn uses_anchor(name: &str, opts: &Options) -> bool {
+  let mut chars = name.chars();
+  opts.draft == Draft::Draft202012
+    && chars
+      .next()
+      .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || "-._".contains(c))
+}
+
+/// Reference to a definition, either by anchor or by JSON pointer. Definitions of other files are
+/// always referenced by pointer, as nothing says those files declare anchors. serde_json takes
+/// care of escaping the string itself, the name only needs escaping as a JSON pointer token
+/// (RFC 6901).
+fn ref_pointer(name: &str, opts: &Options) -> String {
+  let external = opts.external_refs.get(name);
+
+  if external.is_none() && uses_anchor(name, opts) {
+    format!("#{}", name)
+  } else {
+    let definitions = match external {
+      Some(definitions) => definitions.clone(),
+      None => format!("#/{}", opts.definitions_key()),
+    };
+    format!(
+      "{}/{}",
+      definitions,
+      name.replace('~', "~0").replace('/', "~1")
+    )
+  }
+}
+
+/// Refs to JSON Schema primitives, which unions inline instead of referencing
+const PRIMITIVE_TYPES: [&str; 4] = ["string", "number", "integer", "boolean"];
+
+/// The definitions a type refers to
+pub(crate) fn collect_refs<'a>(prop_type: &'a PropertyType, names: &mut HashSet<&'a String>) {
+  match prop_type {
+    PropertyType::Ref(name) => {
+      names.insert(name);
+    }
+    PropertyType::OneOf(types) => types.iter().for_each(|t| collect_refs(t, names)),
+    PropertyType::ArrayOf(inner) | PropertyType::Not(inner) => collect_refs(inner, names),
+    // Keys are JSON strings whatever they are named, only the values refer to definitions
+    PropertyType::Dict { value, .. } => collect_refs(value, names),
+    PropertyType::Constant(_, _) => {}
+  }
+}
+
+/// The refs of an attribute, or of its inline attributes, which replace its type
+pub(crate) fn property_refs<'a>(prop: &'a Property, names: &mut HashSet<&'a String>) {
+  if prop.attributes.is_empty() {
+    collect_refs(&prop.type_name, names);
+  } else {
+    for attribute in prop.attributes.values() {
+      property_refs(attribute, names);
+    }
+  }
+}
+
+fn referenced_names(schema_docs: &HashMap<String, Schema>) -> HashSet<&String> {
+  let mut names = HashSet::new();
+  for prop in schema_docs
+    .values()
+    .flat_map(|schema| schema.properties.values())
+  {
+    property_refs(prop, &mut names);
+  }
+  names
+}
+
+/// The roots and the definitions they refer to through attribute types, group members and bases,
+/// transitively
+pub fn reachable_definitions<'a>(
+  schema_docs: &'a HashMap<String, Schema>,
+  roots: &'a [String],
+) -> HashSet<&'a String> {
+  let mut reachable = HashSet::new();
+  let mut pending = roots.iter().collect_vec();
+
+  while let Some(name) = pending.pop() {
+    if !reachable.insert(name) {
+      continue;
+    }
+    if let Some(schema) = schema_docs.get(name) {
+      let mut names = HashSet::new();
+      for prop in schema.properties.values() {
+        property_refs(prop, &mut names);
+      }
+      pending.extend(names);
+      pending.extend(&schema.group_members);
+      pending.extend(&schema.extends);
+    }
+  }
+
+  reachable
+}
+
+fn primitive_type_name(prop_type: &PropertyType) -> Option<&str> {
+  match prop_type {
+    PropertyType::Ref(name) if PRIMITIVE_TYPES.contains(&name.as_str()) => Some(name),
+    _ => None,
+  }
+}
+
+/// Enums whose values carry their own docs, or string enums with `enum_as_oneof`. `const` only
+/// exists since draft-06, so draft-04 gets a plain `enum` with the value docs collected into a
+/// `$comment`. With `enum_as_oneof` values are titled by their docs, or title-cased.
+fn documented_enum(constants: &[(&String, &Option<String>)], opts: &Options) -> Value {
+  // Values that only differ in case become duplicates once normalized, the first one is kept
+  let constants = constants
+    .iter()
+    .map(|(value, docs)| (constant_to_json(value, opts), *docs))
+    .unique_by(|(value, _)| value.to_string())
+    .collect_vec();
+  let values = constants
+    .iter()
+    .map(|(value, _)| value.clone())
+    .collect_vec();
+
+  if opts.draft >= Draft::Draft06 {
+    let variants = constants
+      .iter()
+      .map(|(value, docs)| match (docs, value.as_str()) {
+        (Some(docs), _) if opts.enum_as_oneof => json!({"const": value, "title": docs}),
+        (Some(docs), _) => json!({"const": value, "description": docs}),
+        (None, Some(text)) if opts.enum_as_oneof => json!({
+          "const": value,
+          "title": definition_title(text, &opts.title_overrides)
+        }),
+        (None, _) => json!({ "const": value }),
+      })
+      .collect_vec();
+    json!({"type":constants_type(&values),"oneOf":variants})
+  } else {
+    let comment = constants
+      .iter()
+      .filter_map(|(value, docs)| docs.as_ref().map(|docs| format!("`{}`: {}", value, docs)))
+      .join("\n");
+    json!({
+      "type":constants_type(&values),
+      "enum":values,
+      "$comment":comment
+    })
+  }
+}
+
+/// The schema of an attribute, including the length bounds of string attributes and the
+/// `multipleOf` of numeric ones. Bounded types are inlined, as keywords next to a `$ref` are
+/// ignored before 2019-09.
+fn property_to_jsonschema(prop: &Property, opts: &Options) -> Value {
+  fn bounded(prop_type: &PropertyType, prop: &Property) -> Option<Value> {
+    match prop_type {
+      PropertyType::Ref(name) if name == "string" => {
+        let mut schema = json!({"type":"string"});
+        if let Some(min) = prop.min_length {
+          schema["minLength"] = json!(min);
+        }
+        if let Some(max) = prop.max_length {
+          schema["maxLength"] = json!(max);
+        }
+        Some(schema)
+      }
+      PropertyType::Ref(name) if name == "number" || name == "integer" => {
+        let multiple_of = prop.multiple_of.as_ref()?.parse::<f64>().ok()?;
+        Some(json!({"type":name,"multipleOf":multiple_of}))
+      }
+      PropertyType::ArrayOf(inner) => {
+        bounded(inner, prop).map(|items| json!({"type":"array","items":items}))
+      }
+      PropertyType::OneOf(types) if types.len() == 1 => bounded(&types[0], prop),
+      _ => None,
+    }
+  }
+
+  let bounds = prop.min_length.is_some() || prop.max_length.is_some() || prop.multiple_of.is_some();
+  let mut schema = match bounded(&prop.type_name, prop).filter(|_| bounds) {
+    // Inline attributes stand for the documented type
+    _ if !prop.attributes.is_empty() => {
+      let mut object = object_schema(prop.attributes.iter(), opts);
+      object["additionalProperties"] = json!(false);
+      let mut schema = if prop.list {
+        json!({"type":"array","items":object})
+      } else {
+        object
+      };
+      merge(&mut schema, &json!({ "description": prop.docs }));
+      schema
+    }
+    Some(mut schema) => {
+      merge(&mut schema, &json!({ "description": prop.docs }));
+      schema
+    }
+    None => prop_type_to_jsonschema(&prop.type_name, Some(&prop.docs), opts),
+  };
+  merge(&mut schema, &extensions_to_jsonschema(&prop.extensions));
+  if let Some(media_type) = &prop.content_media_type {
+    if opts.draft >= Draft::Draft07 {
+      merge(&mut schema, &json!({ "contentMediaType": media_type }));
+    } else {
+      log::warn!(
+        "contentMediaType {} needs draft-07 or later, leaving it out",
+        media_type
+      );
+    }
+  }
+  if let Some(default) = &prop.default {
+    // Defaults that read as JSON, like `0` or `true`, keep their type
+    let default = serde_json::from_str(default).unwrap_or_else(|_| json!(default));
+    merge(&mut schema, &json!({ "default": default }));
+  }
+  let examples = json_examples(prop)
+    .map(|(_, example)| example)
+    .collect_vec();
+  if !examples.is_empty() {
+    merge(&mut schema, &json!({ "examples": examples }));
+  } else if let Some(first) = first_enum_value(&schema).filter(|_| opts.auto_enum_examples) {
+    merge(&mut schema, &json!({ "examples": [first] }));
+  }
+  // `$comment` only exists since draft-07
+  let comments = prop
+    .note
+    .iter()
+    .chain(&prop.source_text)
+    .filter(|_| opts.draft >= Draft::Draft07);
+  for comment in comments {
+    let comment = match schema.get("$comment").and_then(Value::as_str) {
+      Some(existing) if !existing.is_empty() => format!("{}\n\n{}", existing, comment),
+      _ => comment.clone(),
+    };
+    merge(&mut schema, &json!({ "$comment": comment }));
+  }
+  schema
+}
+
+/// The first value of a string enum, written as `enum` or as a `oneOf` of documented consts
+fn first_enum_value(schema: &Value) -> Option<Value> {
+  if schema.get("type")? != "string" {
+    return None;
+  }
+  match (schema.get("enum"), schema.get("oneOf")) {
+    (Some(values), _) => values.get(0).cloned(),
+    (None, Some(variants)) => variants.get(0)?.get("const").cloned(),
+    (None, None) => None,
+  }
+}
+
+/// Extensions are validated with the model, see `model_problems`
+fn extensions_to_jsonschema(extensions: &BTreeMap<String, String>) -> Value {
+  extensions
+    .iter()
+    .map(|(key, value)| (key.clone(), serde_json::from_str(value).unwrap()))
+    .collect::<serde_json::Map<_, _>>()
+    .into()
+}
+
+fn prop_type_to_jsonschema_nodocs(prop_type: &PropertyType, opts: &Options) -> Value {
+  prop_type_to_jsonschema(prop_type, None, opts)
+}
+
+fn prop_type_to_jsonschema(
+  prop_type: &PropertyType,
+  description: Option<&String>,
+  opts: &Options,
+) -> Value {
+  let mut prop_schema = match prop_type {
+    PropertyType::OneOf(types) => {
+      let all_types_count = types.len();
+
+      assert!(all_types_count > 0);
+
+      let all_constants = types
+        .iter()
+        .filter_map(|p| match p {
+          PropertyType::Constant(c, docs) => Some((c, docs)),
+          _ => None,
+        })
+        .collect::<Vec<_>>();
+
+      let all_strings = all_constants.iter().map(|(c, _)| c).collect_vec();
+
+      let primitives = types
+        .iter()
+        .filter_map(primitive_type_name)
+        .unique()
+        .collect_vec();
+
+      // `string | number` and the like need no oneOf, a list of types says the same more tersely
+      if all_types_count > 1 && types.iter().all(|t| primitive_type_name(t).is_some()) {
+        log::debug!("Primitive union type {:?}", prop_type);
+        if primitives.len() == 1 {
+          json!({ "type": primitives[0] })
+        } else {
+          json!({ "type": primitives })
+        }
+      } else if all_strings.len() == all_types_count
+        && (all_constants.iter().any(|(_, d)| d.is_some())
+          || (opts.enum_as_oneof
+            && opts.draft >= Draft::Draft06
+            && all_strings
+              .iter()
+              .all(|c| constant_to_json(c, opts).is_string())))
+      {
+        log::debug!("Documented enum type {:?}", prop_type);
+        documented_enum(&all_constants, opts)
+      } else if all_strings.len() == all_types_count {
+        log::debug!("Enum type {:?}", prop_type);
+        let values = constants_to_json(all_strings.iter().copied().copied(), opts);
+        json!({"type":constants_type(&values),"enum":values})
+      } else if all_types_count == 1 {
+        prop_type_to_jsonschema_nodocs(&types[0], opts)
+      } else {
+        // The primitives of a mixed union share one alternative, where the first of them was, so
+        // `string | number | [string]` has a `{"type":["string","number"]}` alternative
+        let primitive_schema = match primitives.len() {
+          1 => json!({ "type": primitives[0] }),
+          _ => json!({ "type": primitives }),
+        };
+        let mut primitive_schema = Some(primitive_schema);
+        json!({"oneOf": types.iter().filter_map(|t| match primitive_type_name(t) {
+          Some(_) => primitive_schema.take(),
+          None => Some(prop_type_to_jsonschema_nodocs(t, opts)),
+        }).collect_vec()})
+      }
+    }
+    PropertyType::ArrayOf(inner) => json!({
+      "type":"array",
+      "items":prop_type_to_jsonschema(inner, description, opts)
+    }),
+    PropertyType::Constant(item, docs) => {
+      let values = vec![constant_to_json(item, opts)];
+      let mut schema = json!({"type":constants_type(&values),"enum":values});
+      if let Some(docs) = docs {
+        merge(&mut schema, &json!({ "description": docs }));
+      }
+      schema
+    }
+    PropertyType::Not(inner) => json!({
+      "not":prop_type_to_jsonschema_nodocs(inner, opts)
+    }),
+    PropertyType::Dict { value, .. } => {
+      let value = match value.as_ref() {
+        PropertyType::OneOf(types) if types.len() == 1 => &types[0],
+        value => value,
+      };
+      let value_schema = match primitive_type_name(value) {
+        Some(primitive) => json!({ "type": primitive }),
+        None => prop_type_to_jsonschema_nodocs(value, opts),
+      };
+      json!({
+        "type":"object",
+        "patternProperties":{".*":value_schema}
+      })
+    }
+    PropertyType::Ref(item) => match opts.primitives.get(item) {
+      Some(primitive) => primitive.clone(),
+      None => json!({
+        "$ref":ref_pointer(item, opts)
+      }),
+    },
+  };
+
+  if let Some(docs) = description {
+    // Draft-04 ignores the siblings of a `$ref`, but not those of an `allOf` holding it
+    if opts.draft == Draft::Draft04 && prop_schema.get("$ref").is_some() {
+      prop_schema = json!({ "allOf": [prop_schema] });
+    }
+    merge(&mut prop_schema, &json!({ "description": docs }))
+  };
+
+  prop_schema
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn schema_docs_with(contents: &str, opts: &crate::convert::Options) -> HashMap<String, Schema> {
+    let doc = crate::lit::parse(contents).unwrap();
+    crate::convert::to_jsonschemas(&doc, "test.lit", contents, opts)
+      .unwrap()
+      .into_iter()
+      .map(|schema| (schema.schema_name.clone(), schema))
+      .collect()
+  }
+
+  fn schema_docs(contents: &str) -> HashMap<String, Schema> {
+    schema_docs_with(contents, &Default::default())
+  }
+
+  const IDENTICAL: &str = r"\schema{pipeline}{
+    \optional-attribute{a}{foo}{A.}
+    \optional-attribute{b}{bar}{B.}
+  }
+  \schema{foo}{\required-attribute{x}{string}{X.}}
+  \schema{bar}{\required-attribute{x}{string}{X.}}";
+
+  #[test]
+  fn definitions_are_titled_after_their_name() {
+    let overrides = builtin_title_overrides();
+    assert_eq!(definition_title("env_vars", &overrides), "Env Vars");
+    assert_eq!(definition_title("webhook_url", &overrides), "Webhook URL");
+
+    let mut overrides = HashMap::new();
+    overrides.insert("url".to_string(), "URL".to_string());
+    assert_eq!(definition_title("url", &overrides), "URL");
+    assert_eq!(definition_title("url", &HashMap::new()), "Url");
+  }
+
+  fn draft07() -> Options {
+    Options {
+      draft: Draft::Draft07,
+      ..Options::default()
+    }
+  }
+
+  #[test]
+  fn required_if_emits_if_then_with_the_sibling_typed_value() {
+    let docs = schema_docs(
+      r"\schema{pipeline}{
+        \optional-attribute{tls}{boolean}{TLS.}
+        \optional-attribute{mode}{`plain` | `secure`}{Mode.}
+        \optional-attribute{ca}{string}{\required-if{tls}{`true`}}
+        \optional-attribute{key}{string}{\required-if{mode}{`secure`}}
+      }",
+    );
+    let schema = build_schema(&docs, &draft07());
+    let pipeline = &schema["definitions"]["pipeline"];
+    assert_eq!(pipeline.get("required"), Some(&json!([])));
+    assert_eq!(
+      pipeline["allOf"],
+      json!([
+        {
+          "if": {"properties": {"tls": {"enum": [true]}}, "required": ["tls"]},
+          "then": {"required": ["ca"]}
+        },
+        {
+          "if": {"properties": {"mode": {"enum": ["secure"]}}, "required": ["mode"]},
+          "then": {"required": ["key"]}
+        }
+      ])
+    );
+  }
+
+  #[test]
+  fn external_definitions_are_referenced_by_pointer() {
+    let docs = schema_docs(r"\schema{pipeline}{\optional-attribute{a}{foo}{A.}}");
+    let mut opts = Options {
+      draft: Draft::Draft202012,
+      ..Options::default()
+    };
+    opts
+      .external_refs
+      .insert("foo".to_string(), "ext.json#/definitions".to_string());
+    let schema = build_schema(&docs, &opts);
+    assert_eq!(
+      schema["$defs"]["pipeline"]["properties"]["a"]["$ref"],
+      "ext.json#/definitions/foo"
+    );
+    assert_eq!(schema["$ref"], "#pipeline");
+  }
+
+  #[test]
+  fn ref_base_definitions_get_absolute_refs_and_no_local_definition() {
+    let docs = schema_docs(
+      r"\schema{pipeline}{
+        \optional-attribute{a}{string}{A.}
+        \optional-attribute{b}{number}{B.}
+      }",
+    );
+    for draft in [Draft::Draft04, Draft::Draft202012] {
+      let mut opts = Options {
+        draft,
+        ..Options::default()
+      };
+      opts.external_refs.insert(
+        "string".to_string(),
+        "https://x/base.json#/definitions".to_string(),
+      );
+      let schema = build_schema(&docs, &opts);
+      let definitions = &schema[opts.definitions_key()];
+      let props = &definitions["pipeline"]["properties"];
+      let refs = serde_json::to_string(props).unwrap();
+      assert!(
+        refs.contains("https://x/base.json#/definitions/string"),
+        "{}",
+        refs
+      );
+      assert!(!refs.contains("base.json#/definitions/number"));
+      assert!(definitions.get("string").is_none());
+      assert!(definitions.get("number").is_some());
+    }
+  }
+
+  #[test]
+  fn dedup_collapses_definitions_differing_only_in_title() {
+    let opts = Options::default();
+    let mut schema = build_schema(&schema_docs(IDENTICAL), &opts);
+    assert_eq!(schema["definitions"]["foo"]["title"], "Foo");
+    dedup_definitions(&mut schema, &opts);
+    let definitions = schema["definitions"].as_object().unwrap();
+    assert!(definitions.contains_key("bar"));
+    assert!(!definitions.contains_key("foo"));
+    assert_eq!(
+      schema["definitions"]["pipeline"]["properties"]["a"]["allOf"][0]["$ref"],
+      "#/definitions/bar"
+    );
+  }
+
+  /// Nests `value` `depth` objects deep under `key`
+  fn nested(key: &str, depth: usize, mut value: Value) -> Value {
+    for _ in 0..depth {
+      // Not `json!`, which would serialize the value, recursively
+      value = Value::Object([(key.to_string(), value)].into_iter().collect());
+    }
+    value
+  }
+
+  /// Drops a value without recursing, which dropping a deeply nested one would
+  fn dismantle(value: Value) {
+    let mut pending = vec![value];
+    while let Some(value) = pending.pop() {
+      match value {
+        Value::Object(object) => pending.extend(object.into_iter().map(|(_, v)| v)),
+        Value::Array(array) => pending.extend(array),
+        _ => {}
+      }
+    }
+  }
+
+  #[test]
+  fn merge_handles_deeply_nested_objects() {
+    let depth = 100_000;
+    // A deep object replacing a shallow value used to be cloned recursively
+    let mut a = json!({ "a": 1, "x": 1 });
+    let b = nested("a", depth, json!({ "y": [{ "z": 2 }] }));
+    merge(&mut a, &b);
+
+    assert_eq!(a["x"], 1);
+    let mut innermost = &a;
+    for _ in 0..depth {
+      innermost = &innermost["a"];
+    }
+    assert_eq!(innermost, &json!({ "y": [{ "z": 2 }] }));
+
+    dismantle(a);
+    dismantle(b);
+  }
+
+  const VARS: &str = r"\schema{pipeline}{\optional-attribute{vars}{vars}{V.}}\schema{vars}{Vars.}";
+
+  #[test]
+  fn var_names_with_a_space_are_rejected() {
+    let pattern = regex::Regex::new(DEFAULT_VAR_NAME_PATTERN).unwrap();
+    assert!(pattern.is_match("some.var_name-1/x"));
+    assert!(!pattern.is_match("some var"));
+
+    let schema = build_schema(&schema_docs(VARS), &draft07());
+    let vars = &schema["definitions"]["vars"];
+    assert_eq!(vars["propertyNames"]["pattern"], DEFAULT_VAR_NAME_PATTERN);
+
+    // Draft-04 has no propertyNames, only keys matching the pattern are allowed instead
+    let schema = build_schema(&schema_docs(VARS), &Options::default());
+    let vars = &schema["definitions"]["vars"];
+    assert!(vars.get("propertyNames").is_none());
+    assert_eq!(vars["additionalProperties"], false);
+    assert!(vars["patternProperties"][DEFAULT_VAR_NAME_PATTERN].is_object());
+  }
+
+  #[test]
+  fn refs_to_names_with_backslashes_resolve() {
+    let opts = Options::default();
+    let pointer = ref_pointer(r"a\b", &opts);
+    assert_eq!(pointer, r"#/definitions/a\b");
+
+    let schema = json!({ "definitions": { r"a\b": { "type": "string" } } });
+    assert_eq!(
+      schema.pointer(pointer.trim_start_matches('#')),
+      Some(&json!({ "type": "string" }))
+    );
+
+    // Escaped once, by serde_json
+    let mut serialized = vec![];
+    write_json(&json!({ "$ref": pointer }), &mut serialized, &opts).unwrap();
+    assert_eq!(
+      String::from_utf8(serialized).unwrap(),
+      r##"{"$ref":"#/definitions/a\\b"}"##
+    );
+  }
+
+  /// The schema of an attribute of the pipeline, as `contents` documents it
+  fn pipeline_attribute(contents: &str, attribute: &str) -> Value {
+    let schema = build_schema(&schema_docs(contents), &Options::default());
+    schema["definitions"]["pipeline"]["properties"][attribute].clone()
+  }
+
+  #[test]
+  fn primitive_unions_collapse_to_a_list_of_types() {
+    let union = pipeline_attribute(
+      r"\schema{pipeline}{\optional-attribute{a}{string | number | boolean}{A.}}",
+      "a",
+    );
+    assert_eq!(
+      union,
+      json!({ "type": ["string", "number", "boolean"], "description": "A." })
+    );
+  }
+
+  #[test]
+  fn primitives_of_a_mixed_union_share_one_alternative() {
+    let contents = r"\schema{pipeline}{
+      \optional-attribute{a}{string|number}{A.}
+      \optional-attribute{b}{string|[string]|number}{B.}
+    }";
+    assert_eq!(
+      pipeline_attribute(contents, "a"),
+      json!({ "type": ["string", "number"], "description": "A." })
+    );
+    assert_eq!(
+      pipeline_attribute(contents, "b"),
+      json!({
+        "oneOf": [
+          { "type": ["string", "number"] },
+          { "type": "array", "items": { "$ref": "#/definitions/string" } }
+        ],
+        "description": "B."
+      })
+    );
+  }
+
+  /// Every `$ref` in a schema document
+  fn refs(value: &Value) -> Vec<&str> {
+    match value {
+      Value::Object(entries) => entries
+        .iter()
+        .flat_map(|(key, entry)| match entry {
+          Value::String(pointer) if key == "$ref" => vec![pointer.as_str()],
+          entry => refs(entry),
+        })
+        .collect(),
+      Value::Array(items) => items.iter().flat_map(refs).collect(),
+      _ => vec![],
+    }
+  }
+
+  #[test]
+  fn definitions_are_referenced_by_anchor_under_2020_12() {
+    let opts = Options {
+      draft: Draft::Draft202012,
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(IDENTICAL), &opts);
+    let definitions = schema["$defs"].as_object().unwrap();
+
+    let anchors = definitions
+      .iter()
+      .map(|(name, definition)| {
+        assert_eq!(definition["$anchor"], *name);
+        name.as_str()
+      })
+      .collect::<HashSet<_>>();
+    assert_eq!(anchors.len(), definitions.len());
+
+    let refs = refs(&schema);
+    assert!(refs.contains(&"#pipeline") && refs.contains(&"#foo"));
+    for pointer in refs {
+      assert!(
+        anchors.contains(pointer.trim_start_matches('#')),
+        "{}",
+        pointer
+      );
+    }
+  }
+
+  #[test]
+  fn closed_root_rejects_unknown_top_level_keys() {
+    let opts = Options {
+      root_additional_properties: false,
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(IDENTICAL), &opts);
+    assert!(schema.get("$ref").is_none());
+    assert_eq!(
+      schema["allOf"],
+      json!([{ "$ref": "#/definitions/pipeline" }])
+    );
+    // Only the documented keys are known to the root, anything else is an additional property
+    assert_eq!(schema["properties"], json!({ "a": {}, "b": {} }));
+    assert_eq!(schema["additionalProperties"], false);
+
+    let schema = build_schema(&schema_docs(IDENTICAL), &Options::default());
+    assert_eq!(schema["additionalProperties"], true);
+  }
+
+  #[test]
+  fn integral_floats_serialize_like_integers() {
+    let contents = r"\schema{pipeline}{
+      \optional-attribute{a}{`1.0`}{A.}
+      \optional-attribute{b}{`1`}{B.}
+      \optional-attribute{c}{`1.5`}{C.}
+      \optional-attribute{d}{number (default: `1.0`)}{D.}
+      \optional-attribute{e}{number (default: `1`)}{E.}
+    }";
+    let docs = schema_docs(contents);
+    let schema = build_schema(&docs, &Options::default());
+    let props = &schema["definitions"]["pipeline"]["properties"];
+    assert_eq!(props["a"]["enum"], json!([1]));
+    assert_eq!(props["a"]["type"], props["b"]["type"]);
+    assert_eq!(props["a"]["enum"], props["b"]["enum"]);
+    assert_eq!(
+      props["c"],
+      json!({ "type": "number", "enum": [1.5], "description": "C." })
+    );
+
+    let defaults = json!([props["d"]["default"], props["e"]["default"]]);
+    let mut serialized = vec![];
+    write_json(&defaults, &mut serialized, &Options::default()).unwrap();
+    assert_eq!(String::from_utf8(serialized).unwrap(), "[1,1]");
+  }
+
+  #[test]
+  fn value_definition_can_be_overridden_to_a_scalar() {
+    let contents = r"\schema{pipeline}{\optional-attribute{v}{value}{V.}}";
+    let schema = build_schema(&schema_docs(contents), &Options::default());
+    assert_eq!(schema["definitions"]["value"], json!({ "title": "Value" }));
+
+    let scalar = json!({ "type": ["string", "number", "boolean", "null"] });
+    let mut type_map = builtin_type_map();
+    type_map.insert("value".to_string(), scalar.clone());
+    let opts = Options {
+      type_map,
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(contents), &opts);
+    let mut expected = scalar;
+    expected["title"] = json!("Value");
+    assert_eq!(schema["definitions"]["value"], expected);
+  }
+
+  #[test]
+  fn durations_are_matched_by_pattern() {
+    let contents = r"\schema{pipeline}{\optional-attribute{interval}{duration}{I.}}";
+    let schema = build_schema(&schema_docs(contents), &Options::default());
+    let duration = &schema["definitions"]["duration"];
+    assert_eq!(duration["type"], "string");
+
+    let pattern = regex::Regex::new(duration["pattern"].as_str().unwrap()).unwrap();
+    for valid in ["90m", "1h", "1h30m", "500ms", "10µs"] {
+      assert!(pattern.is_match(valid), "{}", valid);
+    }
+    for invalid in ["abc", "90", "m", "1 h"] {
+      assert!(!pattern.is_match(invalid), "{}", invalid);
+    }
+  }
+
+  #[test]
+  fn fragment_is_just_the_definitions_map() {
+    let docs = schema_docs(IDENTICAL);
+    let opts = Options {
+      fragment: true,
+      ..Options::default()
+    };
+    let fragment = build_schema(&docs, &opts);
+    for key in ["$schema", "$ref", "additionalProperties", "definitions"] {
+      assert!(fragment.get(key).is_none(), "{}", key);
+    }
+    assert_eq!(
+      fragment,
+      build_schema(&docs, &Options::default())["definitions"]
+    );
+  }
+
+  #[test]
+  fn empty_and_comment_only_documents_are_a_problem() {
+    for contents in ["", " \n\t\n", "{- nothing here -}\n{- nor here -}"] {
+      assert_eq!(
+        root_problems(&schema_docs(contents)),
+        ["No definitions were found, the schema would be empty"],
+        "{:?}",
+        contents
+      );
+    }
+    assert_eq!(
+      root_problems(&schema_docs(
+        r"\schema{foo}{\required-attribute{x}{string}{X.}}"
+      )),
+      ["No pipeline definition was found, the root of the schema refers to nothing"]
+    );
+    assert!(root_problems(&schema_docs(IDENTICAL)).is_empty());
+  }
+
+  #[test]
+  fn enum_case_normalizes_enum_values() {
+    let contents = r"\schema{pipeline}{\optional-attribute{a}{`Get` | `PUT` | `8080`}{A.}}";
+    let enum_values = |enum_case| {
+      let opts = Options {
+        enum_case,
+        ..Options::default()
+      };
+      let schema = build_schema(&schema_docs(contents), &opts);
+      schema["definitions"]["pipeline"]["properties"]["a"]["enum"].clone()
+    };
+    assert_eq!(enum_values(EnumCase::Preserve), json!(["Get", "PUT", 8080]));
+    assert_eq!(enum_values(EnumCase::Lower), json!(["get", "put", 8080]));
+    assert_eq!(enum_values(EnumCase::Upper), json!(["GET", "PUT", 8080]));
+    assert!("title".parse::<EnumCase>().is_err());
+  }
+
+  #[test]
+  fn empty_schemas_are_objects() {
+    let contents = r"\schema{pipeline}{\optional-attribute{a}{empty}{A.}}\schema{empty}{}";
+    let schema = build_schema(&schema_docs(contents), &Options::default());
+    assert_eq!(
+      schema["definitions"]["empty"],
+      json!({ "type": "object", "title": "Empty" })
+    );
+  }
+
+  #[test]
+  fn dictionary_values_go_to_pattern_properties() {
+    let dict = pipeline_attribute(
+      r"\schema{pipeline}{\optional-attribute{d}{\{resource_name: [version]\}}{D.}}",
+      "d",
+    );
+    assert_eq!(
+      dict,
+      json!({
+        "type": "object",
+        "patternProperties": {
+          ".*": { "type": "array", "items": { "$ref": "#/definitions/version" } }
+        },
+        "description": "D."
+      })
+    );
+  }
+
+  const DEPRECATED_MEMBER: &str = r"\schema{pipeline}{\optional-attribute{s}{step}{S.}}
+  \schema{step}{
+    \schema-group{get step}{get}{\required-attribute{get}{string}{G.}}
+    \schema-group{old step}{old}{\deprecated{Use get.} \required-attribute{old}{string}{O.}}
+  }";
+
+  #[test]
+  fn deprecated_group_members_are_marked_or_dropped() {
+    let docs = schema_docs(DEPRECATED_MEMBER);
+    let schema = build_schema(&docs, &Options::default());
+    let definitions = &schema["definitions"];
+    assert_eq!(definitions["old_step"]["deprecated"], true);
+    assert!(definitions["get_step"].get("deprecated").is_none());
+    assert_eq!(
+      definitions["step"]["oneOf"],
+      json!([
+        { "$ref": "#/definitions/get_step" },
+        { "$ref": "#/definitions/old_step" }
+      ])
+    );
+
+    let opts = Options {
+      drop_deprecated: true,
+      ..Options::default()
+    };
+    let schema = build_schema(&docs, &opts);
+    let step = &schema["definitions"]["step"];
+    assert!(step.get("oneOf").is_none());
+    assert_eq!(step["$ref"], "#/definitions/get_step");
+  }
+
+  #[test]
+  fn numeric_constants_are_numbers() {
+    let contents = r"\schema{pipeline}{\optional-attribute{port}{`8080`}{P.}}";
+    let docs = schema_docs(contents);
+    let port = |opts: &Options| {
+      let schema = build_schema(&docs, opts);
+      schema[opts.definitions_key()]["pipeline"]["properties"]["port"].clone()
+    };
+    assert_eq!(
+      port(&Options::default()),
+      json!({ "type": "integer", "enum": [8080], "description": "P." })
+    );
+    assert_eq!(port(&draft07())["enum"], json!([8080]));
+  }
+
+  #[test]
+  fn group_only_schemas_are_just_the_one_of() {
+    let contents = r"\schema{pipeline}{\optional-attribute{s}{step}{S.}}
+    \schema{step}{
+      \schema-group{get step}{get}{
+        \required-attribute{get}{string}{G.}
+        \optional-attribute{trigger}{boolean}{T.}
+      }
+      \schema-group{put step}{put}{\required-attribute{put}{string}{P.}}
+      \schema-group{task step}{task}{\required-attribute{task}{string}{T.}}
+    }";
+    let docs = schema_docs(contents);
+    assert!(docs["step"].properties.is_empty());
+    assert_eq!(docs["step"].group_members.len(), 3);
+
+    for opts in [Options::default(), draft07()] {
+      let schema = build_schema(&docs, &opts);
+      let definitions = &schema["definitions"];
+      assert_eq!(
+        definitions["step"],
+        json!({
+          "oneOf": [
+            { "$ref": "#/definitions/get_step" },
+            { "$ref": "#/definitions/put_step" },
+            { "$ref": "#/definitions/task_step" }
+          ],
+          "title": "Step"
+        })
+      );
+      // The attributes of the members stay with the members
+      assert_eq!(
+        definitions["get_step"]["required"],
+        json!(["get"]),
+        "{:?}",
+        opts.draft
+      );
+      assert_eq!(definitions["get_step"]["additionalProperties"], false);
+    }
+  }
+
+  #[test]
+  fn property_names_list_the_declared_attributes() {
+    let opts = Options {
+      emit_property_names: true,
+      ..draft07()
+    };
+    let schema = build_schema(&schema_docs(IDENTICAL), &opts);
+    let pipeline = &schema["definitions"]["pipeline"];
+    let declared = pipeline["properties"]
+      .as_object()
+      .unwrap()
+      .keys()
+      .map(|key| json!(key))
+      .collect_vec();
+    assert_eq!(pipeline["additionalProperties"], false);
+    assert_eq!(pipeline["propertyNames"]["enum"], json!(declared));
+
+    let schema = build_schema(&schema_docs(IDENTICAL), &draft07());
+    assert!(schema["definitions"]["pipeline"]
+      .get("propertyNames")
+      .is_none());
+  }
+
+  #[test]
+  fn primitive_definitions_are_always_present() {
+    let contents = r"\schema{pipeline}{\optional-attribute{a}{foo}{A.}}\schema{foo}{}";
+    let opts = Options {
+      seed_definitions: HashMap::from([("bar".to_string(), json!({ "type": "object" }))]),
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(contents), &opts);
+    let definitions = &schema["definitions"];
+    for name in GUARANTEED_DEFINITIONS {
+      assert!(definitions[name].is_object(), "{}", name);
+    }
+    assert_eq!(definitions["string"]["type"], "string");
+    assert_eq!(definitions["bar"]["type"], "object");
+  }
+
+  #[test]
+  fn long_descriptions_are_truncated_keeping_the_full_text() {
+    let mut schema = json!({
+      "description": "The quick brown fox jumps",
+      "properties": { "a": { "description": "Short." } },
+      "default": { "description": "The quick brown fox jumps" }
+    });
+    truncate_descriptions(&mut schema, 12);
+    assert_eq!(schema["description"], "The quick…");
+    assert_eq!(schema["x-full-description"], "The quick brown fox jumps");
+    assert_eq!(
+      schema["properties"]["a"],
+      json!({ "description": "Short." })
+    );
+    // Values are left as they are
+    assert_eq!(
+      schema["default"]["description"],
+      "The quick brown fox jumps"
+    );
+
+    let mut schema = json!({ "description": "Supercalifragilistic" });
+    truncate_descriptions(&mut schema, 5);
+    assert_eq!(schema["description"], "Super…");
+  }
+
+  #[test]
+  fn bundle_inlines_refs_and_keeps_cycles_as_refs() {
+    let contents = r"\schema{pipeline}{
+      \optional-attribute{a}{foo}{A.}
+      \optional-attribute{n}{node}{N.}
+    }
+    \schema{foo}{\required-attribute{x}{string}{X.}}
+    \schema{node}{\optional-attribute{children}{[node]}{C.}}";
+    let opts = draft07();
+    let mut schema = build_schema(&schema_docs(contents), &opts);
+    bundle(&mut schema, &opts);
+
+    // The root keeps additionalProperties next to the pipeline it inlines
+    assert!(schema.get("$ref").is_none());
+    let props = &schema["allOf"][0]["properties"];
+    assert_eq!(props["a"]["title"], "Foo");
+    assert_eq!(props["a"]["description"], "A.");
+    assert_eq!(props["a"]["properties"]["x"]["type"], "string");
+
+    // The ref back into node stays a ref, to the only definition that is kept
+    let node = &props["n"];
+    assert_eq!(
+      node["properties"]["children"]["items"]["$ref"],
+      "#/definitions/node"
+    );
+    let kept = schema["definitions"].as_object().unwrap();
+    assert_eq!(kept.keys().collect_vec(), ["node"]);
+    assert_eq!(
+      kept["node"]["properties"]["children"]["items"]["$ref"],
+      "#/definitions/node"
+    );
+  }
+
+  #[test]
+  fn custom_definitions_key_is_used_by_every_pointer() {
+    let opts = Options {
+      definitions_key: Some("types".to_string()),
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(IDENTICAL), &opts);
+    assert!(schema.get("definitions").is_none());
+    let definitions = schema["types"].as_object().unwrap();
+
+    let refs = refs(&schema);
+    assert!(refs.contains(&"#/types/pipeline") && refs.contains(&"#/types/foo"));
+    for pointer in refs {
+      let name = pointer.strip_prefix("#/types/").unwrap();
+      assert!(definitions.contains_key(name), "{}", pointer);
+    }
+  }
+
+  #[test]
+  fn hyphenated_group_members_and_refs_resolve() {
+    let contents = r"\schema{pipeline}{
+      \optional-attribute{s}{step}{S.}
+      \optional-attribute{g}{get-step}{G.}
+    }
+    \schema{step}{\schema-group{get-step}{get}{\required-attribute{get}{string}{G.}}}";
+    let docs = schema_docs(contents);
+    assert_eq!(docs["step"].group_members, ["get_step"]);
+
+    let schema = build_schema(&docs, &draft07());
+    let definitions = schema["definitions"].as_object().unwrap();
+    assert!(definitions.contains_key("get_step"));
+    let refs = refs(&schema);
+    assert!(refs.contains(&"#/definitions/get_step"));
+    for pointer in refs {
+      let name = pointer.strip_prefix("#/definitions/").unwrap();
+      assert!(definitions.contains_key(name), "{}", pointer);
+    }
+  }
+
+  #[test]
+  fn sanitizing_closes_unclosed_code_spans_and_emphasis() {
+    let mut schema = json!({
+      "description": "Set `name to\nsomething.\n\nThen **stop.\n\n```\n`kept open\n```",
+      "properties": { "a": { "description": "Fine `code`." } }
+    });
+    sanitize_descriptions(&mut schema);
+    assert_eq!(
+      schema["description"],
+      "Set `name to\nsomething.`\n\nThen **stop.**\n\n```\n`kept open\n```"
+    );
+    assert_eq!(schema["properties"]["a"]["description"], "Fine `code`.");
+  }
+
+  #[test]
+  fn writer_and_string_serializations_match() {
+    let docs = schema_docs(IDENTICAL);
+    for opts in [
+      Options::default(),
+      Options {
+        indent: Some(2),
+        ..draft07()
+      },
+    ] {
+      let mut written = vec![];
+      serialize_to_writer(&docs, &mut written, &opts).unwrap();
+      assert_eq!(String::from_utf8(written).unwrap(), serialize(&docs, &opts));
+    }
+  }
+
+  #[test]
+  fn embedded_source_is_the_attribute_markup() {
+    let contents = "\\schema{pipeline}{\n  \\optional-attribute{a}{string}{The \\bold{a}.}\n}";
+    let convert_opts = crate::convert::Options {
+      embed_source: true,
+      ..Default::default()
+    };
+    let docs = schema_docs_with(contents, &convert_opts);
+    let schema = build_schema(&docs, &draft07());
+    assert_eq!(
+      schema["definitions"]["pipeline"]["properties"]["a"]["$comment"],
+      r"\optional-attribute{a}{string}{The \bold{a}.}"
+    );
+
+    // Draft-04 has no `$comment`
+    let schema = build_schema(&docs, &Options::default());
+    assert!(schema["definitions"]["pipeline"]["properties"]["a"]
+      .get("$comment")
+      .is_none());
+
+    let schema = build_schema(&schema_docs(contents), &draft07());
+    assert!(schema["definitions"]["pipeline"]["properties"]["a"]
+      .get("$comment")
+      .is_none());
+  }
+
+  #[test]
+  fn examples_not_matching_their_type_are_a_problem() {
+    let docs = schema_docs(
+      r#"\schema{pipeline}{
+        \optional-attribute{n}{number}{N. \example-toggle{Bad}{"five"}}
+        \optional-attribute{m}{number}{M. \example-toggle{Good}{5}}
+        \optional-attribute{s}{[string]}{S. \example-toggle{Bad}{["a", 1]}}
+      }"#,
+    );
+    assert_eq!(
+      example_problems(&docs, &Options::default()),
+      [
+        r#"In schema pipeline, attribute n: example "five" doesn't match its type"#,
+        r#"In schema pipeline, attribute s: example ["a", 1] doesn't match its type"#,
+      ]
+    );
+  }
+
+  #[test]
+  fn minifying_collapses_spaces_outside_code() {
+    let mut schema = json!({
+      "description": "  Runs  of   spaces.\n\n```\nkey:    value\n```\n\n    indented   code\nend  "
+    });
+    minify_descriptions(&mut schema);
+    assert_eq!(
+      schema["description"],
+      "Runs of spaces.\n\n```\nkey:    value\n```\n\n    indented   code\nend"
+    );
+  }
+
+  #[test]
+  fn described_refs_keep_their_description_in_every_draft() {
+    let docs = schema_docs(IDENTICAL);
+    let schema = build_schema(&docs, &Options::default());
+    assert_eq!(
+      schema["definitions"]["pipeline"]["properties"]["a"],
+      json!({ "allOf": [{ "$ref": "#/definitions/foo" }], "description": "A." })
+    );
+
+    let schema = build_schema(&docs, &draft07());
+    assert_eq!(
+      schema["definitions"]["pipeline"]["properties"]["a"],
+      json!({ "$ref": "#/definitions/foo", "description": "A." })
+    );
+
+    // Siblings of an anchor ref, which must name an anchor of the document
+    let opts = Options {
+      draft: Draft::Draft202012,
+      ..Options::default()
+    };
+    let schema = build_schema(&docs, &opts);
+    assert_eq!(
+      schema["$defs"]["pipeline"]["properties"]["a"],
+      json!({ "$ref": "#foo", "description": "A." })
+    );
+    assert_eq!(schema["$defs"]["foo"]["$anchor"], "foo");
+  }
+
+  #[test]
+  fn comments_before_attributes_become_their_comment() {
+    let contents = r"\schema{pipeline}{
+      {- Kept for 6.x pipelines -}
+      \optional-attribute{a}{string}{The a.}
+      {- @x: {} -}
+      \optional-attribute{b}{string}{The b.}
+    }";
+    let convert_opts = crate::convert::Options {
+      comment_notes: true,
+      ..Default::default()
+    };
+    let docs = schema_docs_with(contents, &convert_opts);
+    let schema = build_schema(&docs, &draft07());
+    let props = &schema["definitions"]["pipeline"]["properties"];
+    assert_eq!(props["a"]["$comment"], "Kept for 6.x pipelines");
+    assert_eq!(props["a"]["description"], "The a.");
+    // Directives aren't notes
+    assert!(props["b"].get("$comment").is_none());
+
+    let schema = build_schema(&docs, &Options::default());
+    assert!(schema["definitions"]["pipeline"]["properties"]["a"]
+      .get("$comment")
+      .is_none());
+
+    let schema = build_schema(&schema_docs(contents), &draft07());
+    assert!(schema["definitions"]["pipeline"]["properties"]["a"]
+      .get("$comment")
+      .is_none());
+  }
+
+  #[test]
+  fn list_of_turns_a_scalar_ref_into_an_array() {
+    let contents = r"\schema{pipeline}{
+      \optional-attribute{r}{resource}{\list-of{The resources.}}
+      \optional-attribute{b}{[resource]}{\list-of{Already a list.}}
+    }
+    \schema{resource}{\required-attribute{name}{string}{N.}}";
+    let list = pipeline_attribute(contents, "r");
+    assert_eq!(list["type"], "array");
+    assert_eq!(list["items"], json!({ "$ref": "#/definitions/resource" }));
+    assert_eq!(
+      list["description"].as_str().map(str::trim),
+      Some("The resources.")
+    );
+    // Brackets already make a list, which isn't nested once more
+    assert_eq!(pipeline_attribute(contents, "b")["items"], list["items"]);
+  }
+
+  #[test]
+  fn version_definition_can_allow_numeric_values() {
+    let contents = r"\schema{pipeline}{\optional-attribute{v}{version}{V.}}";
+    let schema = build_schema(&schema_docs(contents), &Options::default());
+    let version = &schema["definitions"]["version"];
+    assert_eq!(
+      version["patternProperties"][".*"],
+      json!({ "type": "string" })
+    );
+
+    let numeric = json!({
+      "type": "object",
+      "patternProperties": { ".*": { "type": ["string", "number"] } }
+    });
+    let mut type_map = builtin_type_map();
+    type_map.insert("version".to_string(), numeric);
+    let opts = Options {
+      type_map,
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(contents), &opts);
+    assert_eq!(
+      schema["definitions"]["version"]["patternProperties"][".*"]["type"],
+      json!(["string", "number"])
+    );
+  }
+
+  #[test]
+  fn multiple_of_is_emitted_on_numbers() {
+    let contents = r"\schema{pipeline}{
+      \optional-attribute{n}{integer}{N. \multiple-of{512}}
+      \optional-attribute{f}{number}{F. \multiple-of{0.5}}
+      \optional-attribute{ns}{[integer]}{Ns. \multiple-of{4}}
+      \optional-attribute{s}{string}{S. \multiple-of{2}}
+    }";
+    let n = pipeline_attribute(contents, "n");
+    assert_eq!(n["type"], "integer");
+    assert_eq!(n["multipleOf"], 512);
+    assert_eq!(pipeline_attribute(contents, "f")["multipleOf"], 0.5);
+    assert_eq!(
+      pipeline_attribute(contents, "ns")["items"],
+      json!({ "type": "integer", "multipleOf": 4 })
+    );
+    assert!(!pipeline_attribute(contents, "s")
+      .to_string()
+      .contains("multipleOf"));
+  }
+
+  #[test]
+  fn registered_primitives_are_inlined() {
+    let port = json!({ "type": "integer", "minimum": 1, "maximum": 65535 });
+    let opts = Options {
+      primitives: HashMap::from([("port".to_string(), port.clone())]),
+      ..Options::default()
+    };
+    let docs = schema_docs(
+      r"\schema{pipeline}{\optional-attribute{p}{port}{P.}\optional-attribute{ps}{[port]}{Ps.}}",
+    );
+    let schema = build_schema(&docs, &opts);
+    let props = &schema["definitions"]["pipeline"]["properties"];
+    let mut described = port.clone();
+    described["description"] = json!("P.");
+    assert_eq!(props["p"], described);
+    assert_eq!(props["ps"]["items"], port);
+    assert!(schema["definitions"].get("port").is_none());
+    assert!(dangling_refs(&docs, &opts).is_empty());
+  }
+
+  const ENUM: &str = r#"\schema{pipeline}{
+    \optional-attribute{mode}{`fast` | `slow_mode`}{M.}
+    \optional-attribute{given}{`a` | `b`}{G. \example-toggle{B}{"b"}}
+    \optional-attribute{port}{`8080` | `8443`}{P.}
+    \optional-attribute{level}{`low` {- Barely -} | `high`}{L.}
+  }"#;
+
+  #[test]
+  fn auto_enum_examples_use_the_first_value() {
+    let opts = Options {
+      auto_enum_examples: true,
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(ENUM), &opts);
+    let props = &schema["definitions"]["pipeline"]["properties"];
+    assert_eq!(props["mode"]["examples"], json!(["fast"]));
+    // Examples of their own take precedence
+    assert_eq!(props["given"]["examples"], json!(["b"]));
+    // Only string enums are sampled
+    assert_eq!(props["port"]["enum"], json!([8080, 8443]));
+    assert!(props["port"].get("examples").is_none());
+
+    // Enums of documented values are sampled too
+    let schema = build_schema(
+      &schema_docs(ENUM),
+      &Options {
+        auto_enum_examples: true,
+        ..draft07()
+      },
+    );
+    let level = &schema["definitions"]["pipeline"]["properties"]["level"];
+    assert!(level.get("enum").is_none());
+    assert_eq!(level["examples"], json!(["low"]));
+
+    let schema = build_schema(&schema_docs(ENUM), &Options::default());
+    assert!(schema["definitions"]["pipeline"]["properties"]["mode"]
+      .get("examples")
+      .is_none());
+  }
+
+  #[test]
+  fn enum_as_oneof_emits_titled_consts() {
+    let opts = Options {
+      enum_as_oneof: true,
+      ..draft07()
+    };
+    let schema = build_schema(&schema_docs(ENUM), &opts);
+    let mode = &schema["definitions"]["pipeline"]["properties"]["mode"];
+    assert_eq!(
+      mode["oneOf"],
+      json!([
+        { "const": "fast", "title": "Fast" },
+        { "const": "slow_mode", "title": "Slow Mode" }
+      ])
+    );
+    assert!(mode.get("enum").is_none());
+    let props = &schema["definitions"]["pipeline"]["properties"];
+    // The docs of a value are its title
+    assert_eq!(
+      props["level"]["oneOf"],
+      json!([
+        { "const": "low", "title": "Barely" },
+        { "const": "high", "title": "High" }
+      ])
+    );
+    assert_eq!(props["port"]["enum"], json!([8080, 8443]));
+
+    let schema = build_schema(&schema_docs(ENUM), &draft07());
+    let mode = &schema["definitions"]["pipeline"]["properties"]["mode"];
+    assert_eq!(mode["enum"], json!(["fast", "slow_mode"]));
+
+    // const doesn't exist before draft-06
+    let opts = Options {
+      enum_as_oneof: true,
+      ..Options::default()
+    };
+    let schema = build_schema(&schema_docs(ENUM), &opts);
+    let mode = &schema["definitions"]["pipeline"]["properties"]["mode"];
+    assert_eq!(mode["enum"], json!(["fast", "slow_mode"]));
+  }
+
+  #[test]
+  fn nested_attributes_become_an_inline_object() {
+    let docs = schema_docs(
+      r"\schema{pipeline}{
+        \optional-attribute{retry}{config}{How to retry.
+          \required-attribute{attempts}{number}{How often.}
+          \optional-attribute{backoff}{duration}{How long to wait.}
+        }
+        \optional-attribute{hooks}{[config]}{The hooks.
+          \required-attribute{on}{string}{When.}
+        }
+      }",
+    );
+    // Not detached as orphans
+    assert_eq!(docs.keys().collect_vec(), ["pipeline"]);
+
+    let schema = build_schema(&docs, &Options::default());
+    let props = &schema["definitions"]["pipeline"]["properties"];
+    assert_eq!(
+      props["retry"],
+      json!({
+        "type": "object",
+        "properties": {
+          "attempts": { "allOf": [{ "$ref": "#/definitions/number" }], "description": "How often." },
+          "backoff": {
+            "allOf": [{ "$ref": "#/definitions/duration" }],
+            "description": "How long to wait."
+          }
+        },
+        "required": ["attempts"],
+        "additionalProperties": false,
+        "description": "How to retry."
+      })
+    );
+    assert_eq!(
+      props["hooks"],
+      json!({
+        "type": "array",
+        "items": {
+          "type": "object",
+          "properties": {
+            "on": { "allOf": [{ "$ref": "#/definitions/string" }], "description": "When." }
+          },
+          "required": ["on"],
+          "additionalProperties": false
+        },
+        "description": "The hooks."
+      })
+    );
+  }
 }
@@ -0,0 +1,67 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Value};
+
+use crate::schema::types::{Coercion, PropertyType, Schema};
+
+fn count_variants(prop_type: &PropertyType, counts: &mut BTreeMap<&'static str, usize>) {
+  let variant = match prop_type {
+    PropertyType::OneOf(types) => {
+      types.iter().for_each(|t| count_variants(t, counts));
+      "one_of"
+    }
+    PropertyType::Constant(_, _) => "constant",
+    PropertyType::Ref(_) => "ref",
+    PropertyType::ArrayOf(inner) => {
+      count_variants(inner, counts);
+      "array_of"
+    }
+    PropertyType::Not(inner) => {
+      count_variants(inner, counts);
+      "not"
+    }
+    PropertyType::Dict { key, value } => {
+      count_variants(key, counts);
+      count_variants(value, counts);
+      "dict"
+    }
+  };
+  *counts.entry(variant).or_default() += 1;
+}
+
+/// Counts the type variants, required and optional attributes and string coercions of all
+/// attributes, nested types included
+pub fn stats(schema_docs: &HashMap<String, Schema>) -> Value {
+  let mut variants = BTreeMap::new();
+  let (mut required, mut optional) = (0, 0);
+  let (mut dotted, mut unparseable) = (0, 0);
+
+  for prop in schema_docs
+    .values()
+    .flat_map(|schema| schema.properties.values())
+  {
+    count_variants(&prop.type_name, &mut variants);
+    if prop.required {
+      required += 1;
+    } else {
+      optional += 1;
+    }
+    match prop.coercion {
+      Some(Coercion::DottedName) => dotted += 1,
+      Some(Coercion::Unparseable) => unparseable += 1,
+      None => {}
+    }
+  }
+
+  json!({
+    "definitions": schema_docs.len(),
+    "attributes": required + optional,
+    "required": required,
+    "optional": optional,
+    "types": variants,
+    "coerced_to_string": {
+      "dotted_name": dotted,
+      "unparseable": unparseable
+    }
+  })
+}
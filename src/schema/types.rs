@@ -1,24 +1,89 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Debug, PartialEq, Eq)]
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the synthetic schemas holding attributes documented outside of any schema
+pub const ORPHAN_PREFIX: &str = "$orphaned:";
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Schema {
   pub schema_name: String,
   pub is_group_member: bool,
   pub group_members: Vec<String>,
+  pub docs: String,
+  pub extends: Option<String>,
+  /// Property whose value tells the members of a group apart
+  pub discriminator: Option<String>,
+  /// Vendor `x-` keys, mapped to their value as JSON
+  pub extensions: BTreeMap<String, String>,
+  /// Resource type whose `source` this schema describes
+  pub resource_type: Option<String>,
+  /// Complete examples of the whole definition
+  pub examples: Vec<String>,
+  /// Whether the docs mark the schema `\deprecated`
+  pub deprecated: bool,
+  /// The attributes of each block documenting the schema, when several blocks were merged into
+  /// it; `properties` holds all of them
+  pub layers: Vec<HashMap<String, Property>>,
+  pub source: SourceLocation,
   pub properties: HashMap<String, Property>,
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default, Serialize, Deserialize)]
+pub struct SourceLocation {
+  pub file: String,
+  pub line: usize,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Property {
   pub type_name: PropertyType,
   pub required: bool,
   pub list: bool,
   pub docs: String,
+  /// Version the attribute was added in
+  pub since: Option<String>,
+  /// Bounds on the length of string attributes
+  pub min_length: Option<u64>,
+  pub max_length: Option<u64>,
+  /// What numeric attributes must be a multiple of, as written; a positive number
+  pub multiple_of: Option<String>,
+  /// Vendor `x-` keys, mapped to their value as JSON
+  pub extensions: BTreeMap<String, String>,
+  /// Why the documented type was (partly) replaced by `string`, if it was
+  pub coercion: Option<Coercion>,
+  /// Default value given inline in the type, as written
+  pub default: Option<String>,
+  /// Media type of the content embedded in a string attribute, such as `application/x-yaml`
+  pub content_media_type: Option<String>,
+  /// The lit markup documenting the attribute, with `--embed-source`
+  pub source_text: Option<String>,
+  /// Snippets of the `\example-toggle`s in the attribute docs, without repeats
+  pub examples: Vec<String>,
+  /// Attributes the docs define inline, making the attribute (or its items) an object of its own
+  pub attributes: BTreeMap<String, Property>,
+  /// Sibling attributes and the values of them that make this one required, from
+  /// `\required-if{attribute}{value}`
+  pub required_if: Vec<(String, String)>,
+  /// The maintainer comment right before the attribute, with `--comment-notes`
+  pub note: Option<String>,
+}
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum Coercion {
+  /// A dotted name, such as `config.foo`, that isn't kept as a ref
+  DottedName,
+  /// An annotation the type grammar can't parse
+  Unparseable,
 }
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub enum PropertyType {
   OneOf(Vec<PropertyType>),
-  Constant(String),
+  /// A literal value, with the docs of that specific value if any
+  Constant(String, Option<String>),
   Ref(String),
   ArrayOf(Box<PropertyType>),
-  Dict,
+  Not(Box<PropertyType>),
+  /// A map whose keys and values have the given types
+  Dict {
+    key: Box<PropertyType>,
+    value: Box<PropertyType>,
+  },
 }
@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::schema::serialize::{constant_to_json, property_refs, Options};
+use crate::schema::types::{Property, PropertyType, Schema, ORPHAN_PREFIX};
+
+/// TypeScript declarations for the definitions: interfaces for schemas with attributes, unions for
+/// groups and aliases for everything else. Definitions only known to the type map are translated
+/// from their JSON schema.
+pub fn typescript(schema_docs: &HashMap<String, Schema>, opts: &Options) -> String {
+  let mut declarations = schema_docs
+    .values()
+    .filter(|schema| !schema.schema_name.starts_with(ORPHAN_PREFIX))
+    .filter(|schema| primitive(&schema.schema_name).is_none())
+    .sorted_by_key(|schema| &schema.schema_name)
+    .map(|schema| schema_declaration(schema, opts))
+    .collect_vec();
+
+  let mut referenced = HashSet::new();
+  for prop in schema_docs
+    .values()
+    .flat_map(|schema| schema.properties.values())
+  {
+    property_refs(prop, &mut referenced);
+  }
+  for name in referenced.into_iter().sorted() {
+    if schema_docs.contains_key(name) || primitive(name).is_some() {
+      continue;
+    }
+    let alias = opts
+      .type_map
+      .get(name)
+      .map(json_schema_to_ts)
+      .unwrap_or_else(|| "unknown".to_string());
+    declarations.push(format!("export type {} = {};\n", type_name(name), alias));
+  }
+
+  declarations.join("\n")
+}
+
+fn schema_declaration(schema: &Schema, opts: &Options) -> String {
+  let name = type_name(&schema.schema_name);
+  let docs = doc_comment(&schema.docs, "");
+
+  if !schema.group_members.is_empty() {
+    let members = schema
+      .group_members
+      .iter()
+      .map(|m| type_name(m))
+      .join(" | ");
+    return format!("{}export type {} = {};\n", docs, name, members);
+  }
+
+  if schema.properties.is_empty() && schema.extends.is_none() {
+    let alias = match opts.type_map.get(&schema.schema_name) {
+      Some(mapped) => json_schema_to_ts(mapped),
+      None if schema.schema_name == "vars" || schema.docs.is_empty() => {
+        "Record<string, unknown>".to_string()
+      }
+      None => "string".to_string(),
+    };
+    return format!("{}export type {} = {};\n", docs, name, alias);
+  }
+
+  let properties = schema
+    .properties
+    .iter()
+    .sorted_by_key(|(prop_name, _)| *prop_name)
+    .map(|(prop_name, prop)| property_declaration(prop_name, prop, opts))
+    .join("");
+
+  match &schema.extends {
+    // Groups are unions, which interfaces can't extend, so bases are intersected
+    Some(base) => format!(
+      "{}export type {} = {} & {{\n{}}};\n",
+      docs,
+      name,
+      type_name(base),
+      properties
+    ),
+    None => format!("{}export interface {} {{\n{}}}\n", docs, name, properties),
+  }
+}
+
+fn property_declaration(prop_name: &str, prop: &Property, opts: &Options) -> String {
+  let key = if is_identifier(prop_name) {
+    prop_name.to_string()
+  } else {
+    Value::from(prop_name).to_string()
+  };
+  format!(
+    "{}  {}{}: {};\n",
+    doc_comment(&prop.docs, "  "),
+    key,
+    if prop.required { "" } else { "?" },
+    property_type_to_ts(prop, opts)
+  )
+}
+
+/// The type of an attribute, an object literal if its docs define attributes inline
+fn property_type_to_ts(prop: &Property, opts: &Options) -> String {
+  if prop.attributes.is_empty() {
+    return prop_type_to_ts(&prop.type_name, opts);
+  }
+  let attributes = prop
+    .attributes
+    .iter()
+    .map(|(name, attribute)| property_declaration(name, attribute, opts))
+    .join("");
+  // Nested declarations are indented once more per level
+  let attributes = attributes
+    .lines()
+    .map(|line| format!("  {}", line))
+    .join("\n");
+  let object = format!("{{\n{}\n  }}", attributes);
+  if prop.list {
+    format!("{}[]", object)
+  } else {
+    object
+  }
+}
+
+fn prop_type_to_ts(prop_type: &PropertyType, opts: &Options) -> String {
+  match prop_type {
+    PropertyType::OneOf(types) => types
+      .iter()
+      .map(|t| prop_type_to_ts(t, opts))
+      .unique()
+      .join(" | "),
+    PropertyType::Constant(value, _) => constant_to_json(value, opts).to_string(),
+    PropertyType::Ref(name) => primitive(name)
+      .map(String::from)
+      .unwrap_or_else(|| type_name(name)),
+    PropertyType::ArrayOf(inner) => match inner.as_ref() {
+      PropertyType::OneOf(types) if types.len() > 1 => {
+        format!("({})[]", prop_type_to_ts(inner, opts))
+      }
+      _ => format!("{}[]", prop_type_to_ts(inner, opts)),
+    },
+    // TypeScript has no negated types
+    PropertyType::Not(_) => "unknown".to_string(),
+    PropertyType::Dict { value, .. } => format!("Record<string, {}>", prop_type_to_ts(value, opts)),
+  }
+}
+
+/// The TypeScript type of the JSON schemas the type map holds, `unknown` for what it can't express
+fn json_schema_to_ts(schema: &Value) -> String {
+  if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+    return values.iter().map(Value::to_string).join(" | ");
+  }
+
+  let types = match schema.get("type") {
+    Some(Value::String(t)) => vec![t.as_str()],
+    Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+    _ => return "unknown".to_string(),
+  };
+
+  types
+    .into_iter()
+    .map(|t| match t {
+      "string" => "string".to_string(),
+      "number" | "integer" => "number".to_string(),
+      "boolean" => "boolean".to_string(),
+      "null" => "null".to_string(),
+      "array" => "unknown[]".to_string(),
+      "object" => {
+        let value = schema
+          .get("patternProperties")
+          .and_then(Value::as_object)
+          .and_then(|patterns| patterns.values().next())
+          .map(json_schema_to_ts)
+          .unwrap_or_else(|| "unknown".to_string());
+        format!("Record<string, {}>", value)
+      }
+      _ => "unknown".to_string(),
+    })
+    .join(" | ")
+}
+
+fn primitive(name: &str) -> Option<&'static str> {
+  match name {
+    "string" => Some("string"),
+    "number" | "integer" => Some("number"),
+    "boolean" => Some("boolean"),
+    _ => None,
+  }
+}
+
+/// `get_step` becomes `GetStep`
+fn type_name(name: &str) -> String {
+  let name = name
+    .split(|c: char| !c.is_ascii_alphanumeric())
+    .map(|part| {
+      let mut chars = part.chars();
+      match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => "".to_string(),
+      }
+    })
+    .collect::<String>();
+  if name.starts_with(|c: char| c.is_ascii_digit()) {
+    format!("_{}", name)
+  } else {
+    name
+  }
+}
+
+fn is_identifier(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars
+    .next()
+    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn doc_comment(docs: &str, indent: &str) -> String {
+  let docs = docs.trim();
+  if docs.is_empty() {
+    return "".to_string();
+  }
+  let lines = docs
+    .replace("*/", "*\\/")
+    .lines()
+    .map(|line| format!("{} * {}", indent, line).trim_end().to_string())
+    .join("\n");
+  format!("{}/**\n{}\n{} */\n", indent, lines, indent)
+}
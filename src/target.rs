@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::schema::serialize::{Draft, DURATION_PATTERN};
+
+/// Options suiting the pipelines of a Concourse release, for `--target`. Flags and the config file
+/// override them.
+#[derive(Debug, Clone)]
+pub struct Target {
+  pub draft: Draft,
+  pub root_additional_properties: bool,
+  /// Added to the built-in type map, before `--type-map`
+  pub type_map: HashMap<String, Value>,
+}
+
+impl FromStr for Target {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "6" | "6.x" => Ok(Target {
+        draft: Draft::Draft04,
+        root_additional_properties: true,
+        type_map: HashMap::new(),
+      }),
+      // `check_every: never` disables checking since 7.0
+      "7" | "7.x" => Ok(Target {
+        draft: Draft::Draft07,
+        root_additional_properties: true,
+        type_map: HashMap::from([(
+          "duration".to_string(),
+          json!({"anyOf": [
+            {"type":"string","pattern":DURATION_PATTERN},
+            {"enum":["never"]}
+          ]}),
+        )]),
+      }),
+      _ => Err(format!("unknown target {}, expected one of 6.x, 7.x", s)),
+    }
+  }
+}